@@ -1,2 +1,7 @@
 pub mod locktower;
+pub mod simulator;
 extern crate rand;
+#[cfg(feature = "tracing")]
+extern crate tracing;
+#[cfg(all(test, feature = "tracing"))]
+extern crate tracing_subscriber;
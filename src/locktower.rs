@@ -1,5 +1,12 @@
+use std::cmp::{Ordering, Reverse};
 use std::collections::HashMap;
+use std::collections::HashSet;
 use std::collections::VecDeque;
+use std::fmt::{self, Debug};
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
 
 #[derive(Clone, Default, Debug)]
 pub struct Branch {
@@ -7,8 +14,33 @@ pub struct Branch {
     base: usize,
 }
 
+/// branches are identified by id, a branch tree rejects two branches with the same id
+/// and different bases as a conflict
+impl PartialEq for Branch {
+    fn eq(&self, other: &Branch) -> bool {
+        self.id == other.id
+    }
+}
+
+impl Eq for Branch {}
+
+impl Hash for Branch {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+    }
+}
+
 impl Branch {
-    fn is_trunk_of(&self, other: &Branch, branch_tree: &HashMap<usize, Branch>) -> bool {
+    pub fn new(id: usize, base: usize) -> Self {
+        Self { id, base }
+    }
+    pub fn id(&self) -> usize {
+        self.id
+    }
+    pub fn base(&self) -> usize {
+        self.base
+    }
+    fn is_trunk_of(&self, other: &Branch, branch_tree: &BranchTree) -> bool {
         let mut current = other.clone();
         loop {
             // found it
@@ -17,16 +49,457 @@ impl Branch {
             }
             // base is 0, and this id is 0
             if current.base == 0 && self.id == 0 {
-                assert!(branch_tree.get(&0).is_none());
+                assert!(branch_tree.get(0).is_none());
                 return true;
             }
             // base is 0
-            if branch_tree.get(&current.base).is_none() {
+            if branch_tree.get(current.base).is_none() {
                 return false;
             }
-            current = branch_tree.get(&current.base).unwrap().clone();
+            current = branch_tree.get(current.base).unwrap().clone();
+        }
+    }
+    /// `other` is this branch or one of its ancestors, the inverse of `is_trunk_of`
+    pub fn is_descendant_of(&self, other: &Branch, branch_tree: &BranchTree) -> bool {
+        other.is_trunk_of(self, branch_tree)
+    }
+    /// How many branches in the tree until this branch connects to branch 0
+    pub fn depth(&self, branch_tree: &BranchTree) -> usize {
+        let mut depth = 0;
+        let mut start = branch_tree.get(self.id);
+        while let Some(branch) = start {
+            depth += 1;
+            start = branch_tree.get(branch.base);
+        }
+        depth
+    }
+}
+
+/// how a lockout grows when a newer vote reaches the same lockout
+pub trait LockoutSchedule: Debug + Send + Sync {
+    /// lockout of the vote at `depth` that replaces `current_lockout`
+    fn next_lockout(&self, current_lockout: usize, depth: usize) -> usize;
+    fn box_clone(&self) -> Box<dyn LockoutSchedule>;
+}
+
+impl Clone for Box<dyn LockoutSchedule> {
+    fn clone(&self) -> Self {
+        self.box_clone()
+    }
+}
+
+/// the lockout doubles, the default schedule
+#[derive(Clone, Copy, Default, Debug)]
+pub struct DoublingSchedule;
+
+impl LockoutSchedule for DoublingSchedule {
+    fn next_lockout(&self, current_lockout: usize, _depth: usize) -> usize {
+        current_lockout.saturating_mul(2)
+    }
+    fn box_clone(&self) -> Box<dyn LockoutSchedule> {
+        Box::new(*self)
+    }
+}
+
+/// the lockout grows to the next fibonacci number, 2, 3, 5, 8...
+#[derive(Clone, Copy, Default, Debug)]
+pub struct FibonacciSchedule;
+
+impl LockoutSchedule for FibonacciSchedule {
+    fn next_lockout(&self, current_lockout: usize, _depth: usize) -> usize {
+        let (mut a, mut b): (usize, usize) = (1, 2);
+        while b <= current_lockout {
+            let next = match a.checked_add(b) {
+                Some(next) => next,
+                None => return usize::MAX,
+            };
+            a = b;
+            b = next;
+        }
+        b
+    }
+    fn box_clone(&self) -> Box<dyn LockoutSchedule> {
+        Box::new(*self)
+    }
+}
+
+/// `next_lockout` is the function, called with `(current_lockout, depth)`
+#[derive(Clone, Copy, Debug)]
+pub struct CustomSchedule(pub fn(usize, usize) -> usize);
+
+impl LockoutSchedule for CustomSchedule {
+    fn next_lockout(&self, current_lockout: usize, depth: usize) -> usize {
+        (self.0)(current_lockout, depth)
+    }
+    fn box_clone(&self) -> Box<dyn LockoutSchedule> {
+        Box::new(*self)
+    }
+}
+
+/// reason a branch cannot be added to the tree
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BranchTreeError {
+    /// branch 0 is the root and is never stored in the tree
+    RootBranch,
+    /// a branch with the same id but a different base is already in the tree
+    Conflict { id: usize },
+    /// the branch would be its own ancestor
+    Cycle { id: usize },
+    /// the branch is not in the tree
+    NotFound { id: usize },
+    /// the branch's base is already in the tree
+    NotOrphan { id: usize },
+    /// the branch doesn't descend from the finalized branch
+    NotDescendant { id: usize },
+}
+
+/// map of `branch id` to `branch`
+/// branch 0 is the root of the tree and is not stored
+#[derive(Clone, Default, Debug)]
+pub struct BranchTree {
+    branches: HashMap<usize, Branch>,
+    /// the newest finalized branch, `None` until a branch other than the root is finalized
+    finalized: Option<Branch>,
+}
+
+impl BranchTree {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn get(&self, id: usize) -> Option<&Branch> {
+        self.branches.get(&id)
+    }
+    pub fn contains(&self, id: usize) -> bool {
+        self.branches.contains_key(&id)
+    }
+    pub fn len(&self) -> usize {
+        self.branches.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.branches.is_empty()
+    }
+    pub fn finalized(&self) -> Option<&Branch> {
+        self.finalized.as_ref()
+    }
+    /// move the finalized frontier forward to `branch`
+    /// finalizing the root or an ancestor of the finalized branch does nothing
+    pub fn mark_finalized(&mut self, branch: Branch) -> Result<(), BranchTreeError> {
+        if branch.id == 0 || self.is_below_finalized(&branch) {
+            return Ok(());
+        }
+        if !self.branches.contains_key(&branch.id) {
+            return Err(BranchTreeError::NotFound { id: branch.id });
+        }
+        if !self.is_finalized_trunk_of(&branch) {
+            return Err(BranchTreeError::NotDescendant { id: branch.id });
+        }
+        self.finalized = Some(branch);
+        Ok(())
+    }
+    /// `branch` is the finalized branch or one of its descendants
+    pub fn is_finalized_trunk_of(&self, branch: &Branch) -> bool {
+        self.finalized
+            .as_ref()
+            .map(|f| f.is_trunk_of(branch, self))
+            .unwrap_or(true)
+    }
+    /// `branch` is the finalized branch or one of its ancestors
+    fn is_below_finalized(&self, branch: &Branch) -> bool {
+        self.finalized
+            .as_ref()
+            .map(|f| branch.is_trunk_of(f, self))
+            .unwrap_or(false)
+    }
+    /// add a branch to the tree, adding a branch that is already in the tree does nothing
+    pub fn insert(&mut self, branch: Branch) -> Result<(), BranchTreeError> {
+        self.insert_new(branch).map(|_| ())
+    }
+    /// insert all the branches or none of them
+    /// returns the number of branches that were not already in the tree
+    /// on failure the tree is left unchanged and the index of the failing branch is returned
+    pub fn insert_batch(
+        &mut self,
+        branches: Vec<Branch>,
+    ) -> Result<usize, (BranchTreeError, usize)> {
+        let mut inserted = vec![];
+        for (i, branch) in branches.into_iter().enumerate() {
+            let id = branch.id;
+            match self.insert_new(branch) {
+                Ok(true) => inserted.push(id),
+                Ok(false) => (),
+                Err(err) => {
+                    for id in inserted {
+                        self.branches.remove(&id);
+                    }
+                    return Err((err, i));
+                }
+            }
+        }
+        Ok(inserted.len())
+    }
+    /// number of branches at `depth`, the depth of a branch whose base is the root is 1
+    pub fn fork_count_at_depth(&self, depth: usize) -> usize {
+        self.branches
+            .values()
+            .filter(|b| b.depth(self) == depth)
+            .count()
+    }
+    /// depth of the deepest branch in the tree
+    pub fn max_fork_depth(&self) -> usize {
+        self.branches
+            .values()
+            .map(|b| b.depth(self))
+            .max()
+            .unwrap_or(0)
+    }
+    /// ids of every branch in the tree, sorted, the root is not included
+    pub fn branch_ids(&self) -> Vec<usize> {
+        let mut ids: Vec<_> = self.branches.keys().cloned().collect();
+        ids.sort();
+        ids
+    }
+    /// `(base id, id)` of every branch, parents before their children
+    pub fn to_adjacency_list(&self) -> Vec<(usize, usize)> {
+        let mut branches: Vec<_> = self.branches.values().collect();
+        branches.sort_by_key(|b| (b.depth(self), b.id));
+        branches.into_iter().map(|b| (b.base, b.id)).collect()
+    }
+    /// fork choice, walk down from `root_id` always taking the child with the heaviest
+    /// subtree, ties go to the higher id
+    /// returns the branch the walk ends on, `None` if `root_id` is the root and has no children
+    /// * vote_map - map of `branch id` to the weight of the votes for that branch
+    pub fn find_heaviest_fork<'a>(
+        &'a self,
+        vote_map: &HashMap<usize, u64>,
+        root_id: usize,
+    ) -> Option<&'a Branch> {
+        let mut children: HashMap<usize, Vec<&Branch>> = HashMap::new();
+        let mut weights: HashMap<usize, u64> = HashMap::new();
+        let mut branches: Vec<_> = self.branches.values().collect();
+        branches.sort_by_key(|b| Reverse(b.depth(self)));
+        for branch in branches {
+            children.entry(branch.base).or_default().push(branch);
+            let weight = *vote_map.get(&branch.id).unwrap_or(&0);
+            let subtree = {
+                let w = weights.entry(branch.id).or_insert(0);
+                *w += weight;
+                *w
+            };
+            *weights.entry(branch.base).or_insert(0) += subtree;
+        }
+        let mut heaviest = self.get(root_id);
+        let mut current = root_id;
+        while let Some(next) = children.get(&current).and_then(|c| {
+            c.iter()
+                .max_by_key(|b| (weights.get(&b.id).cloned().unwrap_or(0), b.id))
+        }) {
+            heaviest = Some(*next);
+            current = next.id;
+        }
+        heaviest
+    }
+    /// same as `max_fork_depth`
+    pub fn max_depth(&self) -> usize {
+        self.max_fork_depth()
+    }
+    /// index `d` is the number of branches at depth `d`, index 0 is always 0 since the root
+    /// is not in the tree, empty for an empty tree
+    pub fn depth_histogram(&self) -> Vec<usize> {
+        let mut histogram = vec![];
+        for branch in self.branches.values() {
+            let depth = branch.depth(self);
+            if histogram.len() <= depth {
+                histogram.resize(depth + 1, 0);
+            }
+            histogram[depth] += 1;
+        }
+        histogram
+    }
+    /// average depth of the branches in the tree, 0.0 for an empty tree
+    pub fn mean_depth(&self) -> f64 {
+        if self.branches.is_empty() {
+            return 0.0;
+        }
+        let total: usize = self.branches.values().map(|b| b.depth(self)).sum();
+        total as f64 / self.branches.len() as f64
+    }
+    /// branches whose base is not the root and is missing from the tree, ordered by id
+    pub fn orphans(&self) -> Vec<&Branch> {
+        let mut orphans: Vec<_> = self
+            .branches
+            .values()
+            .filter(|b| b.base != 0 && !self.branches.contains_key(&b.base))
+            .collect();
+        orphans.sort_by_key(|b| b.id);
+        orphans
+    }
+    /// connect an orphan to `new_base`
+    pub fn reattach_orphan(
+        &mut self,
+        branch_id: usize,
+        new_base: usize,
+    ) -> Result<(), BranchTreeError> {
+        let base = self
+            .branches
+            .get(&branch_id)
+            .map(|b| b.base)
+            .ok_or(BranchTreeError::NotFound { id: branch_id })?;
+        if base == 0 || self.branches.contains_key(&base) {
+            return Err(BranchTreeError::NotOrphan { id: branch_id });
+        }
+        if self.is_ancestor_id(branch_id, new_base) {
+            return Err(BranchTreeError::Cycle { id: branch_id });
+        }
+        self.branches.get_mut(&branch_id).unwrap().base = new_base;
+        Ok(())
+    }
+    /// lowest common ancestor, the deepest branch that is `a` or `b` or an ancestor of both
+    /// the root is an ancestor of every branch connected to it, orphans have no common
+    /// ancestor with the rest of the tree
+    pub fn lca(&self, a: &Branch, b: &Branch) -> Option<Branch> {
+        let ancestors: HashSet<usize> = self.ancestry(a).iter().map(|b| b.id).collect();
+        self.ancestry(b)
+            .into_iter()
+            .find(|b| ancestors.contains(&b.id))
+    }
+    /// branches that are an ancestor of, or one of, all the `branches`, deepest first
+    /// every branch in the tree and the root is a common ancestor of no branches
+    pub fn common_ancestors(&self, branches: &[&Branch]) -> Vec<Branch> {
+        let (first, rest) = match branches.split_first() {
+            Some(split) => split,
+            None => {
+                let mut all: Vec<_> = self.branches.values().cloned().collect();
+                all.sort_by_key(|b| (Reverse(b.depth(self)), b.id));
+                all.push(Branch::default());
+                return all;
+            }
+        };
+        let others: Vec<HashSet<usize>> = rest
+            .iter()
+            .map(|b| self.ancestry(b).iter().map(|a| a.id).collect())
+            .collect();
+        // the ancestry of a branch is ordered deepest first
+        self.ancestry(first)
+            .into_iter()
+            .filter(|a| others.iter().all(|o| o.contains(&a.id)))
+            .collect()
+    }
+    /// `branch` and its ancestors, ending with the root if the branch connects to it
+    fn ancestry(&self, branch: &Branch) -> Vec<Branch> {
+        let mut ancestry = vec![branch.clone()];
+        if branch.id == 0 {
+            return ancestry;
+        }
+        let mut base = branch.base;
+        while let Some(b) = self.branches.get(&base) {
+            ancestry.push(b.clone());
+            base = b.base;
+        }
+        if base == 0 {
+            ancestry.push(Branch::default());
+        }
+        ancestry
+    }
+    /// `id` is `start` or one of its ancestors
+    fn is_ancestor_id(&self, id: usize, start: usize) -> bool {
+        // the tree has no cycles, so walking up from `start` always ends
+        let mut current = Some(start);
+        while let Some(c) = current {
+            if c == id {
+                return true;
+            }
+            current = self.branches.get(&c).map(|b| b.base);
+        }
+        false
+    }
+    /// returns true if the branch was not already in the tree
+    fn insert_new(&mut self, branch: Branch) -> Result<bool, BranchTreeError> {
+        if branch.id == 0 {
+            return Err(BranchTreeError::RootBranch);
+        }
+        if let Some(old) = self.branches.get(&branch.id) {
+            if old.base != branch.base {
+                return Err(BranchTreeError::Conflict { id: branch.id });
+            }
+            return Ok(false);
+        }
+        if self.is_ancestor_id(branch.id, branch.base) {
+            return Err(BranchTreeError::Cycle { id: branch.id });
+        }
+        self.branches.insert(branch.id, branch);
+        Ok(true)
+    }
+}
+
+/// order branches by depth, a deeper branch is greater
+pub fn branch_depth_cmp(a: &Branch, b: &Branch, branch_tree: &BranchTree) -> Ordering {
+    a.depth(branch_tree).cmp(&b.depth(branch_tree))
+}
+
+/// hands out unique branch ids, id 0 is the root and is never allocated
+#[derive(Debug)]
+pub struct BranchIdAllocator {
+    next: usize,
+    reserved: HashSet<usize>,
+}
+
+impl BranchIdAllocator {
+    pub fn new() -> Self {
+        Self::with_start(1)
+    }
+    /// start allocating at `start`, allocators with disjoint ranges never collide
+    pub fn with_start(start: usize) -> Self {
+        Self {
+            next: start.max(1),
+            reserved: HashSet::new(),
+        }
+    }
+    /// allocate the next id as a child of `base_id`
+    pub fn next(&mut self, base_id: usize) -> Branch {
+        while self.reserved.contains(&self.next) {
+            self.next += 1;
+        }
+        let id = self.next;
+        self.next += 1;
+        Branch { id, base: base_id }
+    }
+    /// keep a well known id from being allocated
+    pub fn reserve(&mut self, id: usize) {
+        self.reserved.insert(id);
+    }
+}
+
+impl Default for BranchIdAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// thread safe `BranchIdAllocator`
+#[derive(Debug)]
+pub struct AtomicBranchIdAllocator {
+    next: AtomicUsize,
+}
+
+impl AtomicBranchIdAllocator {
+    pub fn new() -> Self {
+        Self::with_start(1)
+    }
+    pub fn with_start(start: usize) -> Self {
+        Self {
+            next: AtomicUsize::new(start.max(1)),
         }
     }
+    pub fn next(&self, base_id: usize) -> Branch {
+        let id = self.next.fetch_add(1, AtomicOrdering::SeqCst);
+        Branch { id, base: base_id }
+    }
+}
+
+impl Default for AtomicBranchIdAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 #[derive(Clone, Default, Debug)]
@@ -34,6 +507,8 @@ pub struct Vote {
     branch: Branch,
     time: usize,
     lockout: usize,
+    /// extra data carried with the vote, ignored by the tower
+    memo: Option<[u8; 32]>,
 }
 
 impl Vote {
@@ -42,261 +517,5878 @@ impl Vote {
             branch,
             time,
             lockout: 2,
+            memo: None,
         }
     }
+    pub fn new_with_memo(branch: Branch, time: usize, memo: [u8; 32]) -> Vote {
+        Self {
+            memo: Some(memo),
+            ..Self::new(branch, time)
+        }
+    }
+    pub fn memo(&self) -> Option<[u8; 32]> {
+        self.memo
+    }
+    pub fn branch(&self) -> &Branch {
+        &self.branch
+    }
+    pub fn time(&self) -> usize {
+        self.time
+    }
+    pub fn lockout(&self) -> usize {
+        self.lockout
+    }
+    /// replace the lockout, for repairing a tower through `LockTower::votes_iter_mut`
+    pub fn set_lockout(&mut self, lockout: usize) {
+        self.lockout = lockout;
+    }
+    /// lockout at `depth` in a tower that has doubled on every vote, 0 is the newest vote
+    /// saturates at `usize::MAX` for deep towers
+    pub fn expected_lockout_at_depth(
+        depth: usize,
+        initial_lockout: usize,
+        multiplier: usize,
+    ) -> usize {
+        (0..depth).fold(initial_lockout, |lockout, _| {
+            lockout.saturating_mul(multiplier)
+        })
+    }
     pub fn lock_height(&self) -> usize {
         self.time + self.lockout
     }
-    pub fn is_trunk_of(&self, other: &Vote, branch_tree: &HashMap<usize, Branch>) -> bool {
+    pub fn is_trunk_of(&self, other: &Vote, branch_tree: &BranchTree) -> bool {
         self.branch.is_trunk_of(&other.branch, branch_tree)
     }
+    /// time left in the lockout, this is the TTL for gossiping the vote
+    pub fn slots_until_expiry(&self, current_time: usize) -> Option<usize> {
+        self.lock_height().checked_sub(current_time)
+    }
+    /// the lockout has time left at `time`
+    /// this ignores a tower's grace slots, the `LockTower` queries add them
+    pub fn is_active_at(&self, time: usize) -> bool {
+        self.lock_height() > time
+    }
+}
+
+/// map of `branch id` to `node count`
+/// how many nodes in the network have voted for the branch or one of its descendants
+#[derive(Clone, Default, Debug)]
+pub struct ConvergeMap(HashMap<usize, usize>);
+
+impl ConvergeMap {
+    pub fn from_branch_map(branch_map: &HashMap<usize, usize>) -> Self {
+        ConvergeMap(branch_map.clone())
+    }
+    pub fn set(&mut self, branch_id: usize, count: usize) {
+        self.0.insert(branch_id, count);
+    }
+    pub fn increment(&mut self, branch_id: usize) {
+        *self.0.entry(branch_id).or_insert(0) += 1;
+    }
+    /// missing branches have a count of 0
+    pub fn get(&self, branch_id: usize) -> usize {
+        *self.0.get(&branch_id).unwrap_or(&0)
+    }
+    /// `(branch id, count)` pairs in arbitrary order
+    pub fn iter(&self) -> impl Iterator<Item = (&usize, &usize)> {
+        self.0.iter()
+    }
+}
+
+/// find the branch with the highest count of nodes that have it as an ancestor
+/// ties are broken by the deepest branch, and then by the highest branch id
+/// returns `(branch id, count)`
+pub fn calc_newest_trunk_v2(
+    bmap: &ConvergeMap,
+    branch_tree: &BranchTree,
+) -> Option<(usize, usize)> {
+    let root = Branch::default();
+    bmap.iter()
+        .max_by(|x, y| {
+            let bx = branch_tree.get(*x.0).unwrap_or(&root);
+            let by = branch_tree.get(*y.0).unwrap_or(&root);
+            x.1.cmp(y.1)
+                .then_with(|| branch_depth_cmp(bx, by, branch_tree))
+                .then_with(|| x.0.cmp(y.0))
+        }).map(|(id, count)| (*id, *count))
+}
+
+/// the newest lockout is 2, and each older lockout is larger and can be reached from 2
+/// by growing it with `schedule` at its depth
+fn follows_schedule<I: Iterator<Item = usize>>(
+    schedule: &dyn LockoutSchedule,
+    lockouts: I,
+) -> bool {
+    let mut last = 1;
+    for (depth, lockout) in lockouts.enumerate() {
+        if lockout <= last || (last == 1 && lockout != 2) {
+            return false;
+        }
+        if schedule_lockout_at_least(schedule, depth, lockout) != lockout {
+            return false;
+        }
+        last = lockout;
+    }
+    true
+}
+
+/// the smallest lockout of `schedule` at `depth` that is at least `min`, growing from 2
+/// a schedule that stops growing, or would overflow, before `min` returns its largest lockout
+fn schedule_lockout_at_least(schedule: &dyn LockoutSchedule, depth: usize, min: usize) -> usize {
+    let mut reached = 2;
+    while reached < min && reached <= usize::MAX / 2 {
+        let next = schedule.next_lockout(reached, depth);
+        if next <= reached {
+            break;
+        }
+        reached = next;
+    }
+    reached
+}
+
+/// append `value` as an unsigned LEB128 varint
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+/// read an unsigned LEB128 varint at `*pos` and advance `*pos` past it
+fn read_varint(bytes: &[u8], pos: &mut usize) -> Result<usize, DecodeError> {
+    let mut value: usize = 0;
+    let mut shift = 0;
+    loop {
+        let byte = *bytes.get(*pos).ok_or(DecodeError::Truncated)?;
+        *pos += 1;
+        let bits = (byte & 0x7f) as usize;
+        if shift >= usize::MAX.count_ones() || (bits << shift) >> shift != bits {
+            return Err(DecodeError::InvalidVarint { offset: *pos - 1 });
+        }
+        value |= bits << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+        shift += 7;
+    }
+}
+
+/// rough estimate of the rounds until the whole network is converged
+/// every unconverged node needs a round, and a fraction `fail_rate` of the rounds are lost
+pub fn expected_convergence_rounds(
+    current_convergence: usize,
+    network_size: usize,
+    fail_rate: f64,
+) -> f64 {
+    network_size.saturating_sub(current_convergence) as f64 * (1.0 / (1.0 - fail_rate))
+}
+
+/// fraction of stake that must be on other forks before a voter can switch forks
+pub const SWITCH_THRESHOLD: f64 = 0.38;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SwitchCheckResult {
+    Allowed { stake_fraction: f64 },
+    Blocked { required: f64, actual: f64 },
+}
+
+/// lockout a vote needs before its slot is `CommitmentLevel::Confirmed`
+pub const CONFIRMED_LOCKOUT: usize = 32;
+
+/// highest grade of `LockTower::commitment_grades`
+pub const MAX_COMMITMENT_GRADE: u8 = 8;
+
+/// most voters a `ConvergeMap` count can include, the convergence check is hard coded
+/// to a network of this size
+pub const MAX_NETWORK_SIZE: usize = 100;
+
+/// most votes `LockTower::votes_not_converged` keeps, the oldest are dropped first
+pub const MAX_PENDING_VOTES: usize = 32;
+
+/// how committed the tower is to a slot, same tiers as Solana's commitment levels
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CommitmentLevel {
+    /// the tower hasn't committed to the slot
+    Processed,
+    /// the vote for the slot has reached `CONFIRMED_LOCKOUT`
+    Confirmed,
+    /// the slot's branch is rooted
+    Finalized,
+}
+
+/// reason a vote cannot be pushed to the tower
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum VoteError {
+    /// the vote is not descendent from the last vote in the stack
+    InvalidBranch,
+    /// the vote at the threshold depth doesn't have enough of the network committed
+    NotConverged,
+    /// the tower has already voted past the vote's time
+    OldVote { last_time: usize, vote_time: usize },
+    /// the vote doesn't descend from the branch tree's finalized branch
+    BelowFinalized,
+    /// the tower has already voted at the vote's time
+    DoubleVote { time: usize },
+    /// the vote switches forks too soon after the last fork switch
+    CooldownActive { remaining: usize },
+    /// the vote's time is not after the last vote's time
+    NonMonotonicTime { expected_min: usize, got: usize },
+    /// the vote's slot is too far ahead of the current slot
+    FutureVote { slot: usize, current: usize },
+    /// the vote message's signature doesn't match its voter
+    InvalidSignature,
+}
+
+/// what `LockTower::push_vote_or_wait` expects `push_vote` to do with a vote
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VotePushDecision {
+    VoteNow,
+    /// the tower is locked out, retry the vote after this many slots
+    WaitSlots(usize),
+    /// the vote doesn't descend from the rooted or finalized branch, it is never valid
+    InvalidBranch,
+    NotConverged,
+    /// the tower already voted at or after the vote's time, it is never valid
+    AlreadyVoted,
+}
+
+/// source of the current slot to validate vote times against
+pub trait SlotOracle {
+    fn current_slot(&self) -> usize;
+}
+
+/// a vote signed by `voter_id`, as received over gossip
+#[derive(Clone, Debug)]
+pub struct VoteMessage {
+    pub voter_id: u64,
+    pub vote: Vote,
+    pub signature: [u8; 64],
+}
+
+impl VoteMessage {
+    /// the bytes covered by the signature, the voter id, branch id, branch base and time
+    /// as little endian u64s
+    pub fn signed_bytes(&self) -> Vec<u8> {
+        let vote = &self.vote;
+        let fields = [
+            self.voter_id,
+            vote.branch.id as u64,
+            vote.branch.base as u64,
+            vote.time as u64,
+        ];
+        fields
+            .iter()
+            .flat_map(|f| f.to_le_bytes().to_vec())
+            .collect()
+    }
+}
+
+/// checks the signature of a `VoteMessage`
+pub trait SignatureVerifier {
+    fn verify(&self, msg: &[u8], sig: &[u8; 64], pubkey: u64) -> bool;
+}
+
+/// `SignatureVerifier` that accepts every signature
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoopVerifier;
+
+impl SignatureVerifier for NoopVerifier {
+    fn verify(&self, _msg: &[u8], _sig: &[u8; 64], _pubkey: u64) -> bool {
+        true
+    }
+}
+
+/// `SlotOracle` that always returns the same slot
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MockSlotOracle(pub usize);
+
+impl SlotOracle for MockSlotOracle {
+    fn current_slot(&self) -> usize {
+        self.0
+    }
+}
+
+/// compact tower state that lets a third party check a vote against the tower
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TowerProof {
+    /// `(branch id, lockout)` of each vote, newest first
+    pub votes: Vec<(usize, usize)>,
+    /// time of each vote in `votes`, only needed to rebuild the tower
+    pub times: Vec<usize>,
+    /// id of the rooted branch
+    pub branch_trunk: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ProofError {
+    /// the lockouts don't follow the lockout schedule
+    InvalidLockout,
+    /// the vote is not descendent from the last vote in the proof
+    InvalidBranch,
+}
+
+/// reason exported votes cannot be imported into a tower
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ImportError {
+    /// a vote is older than the vote exported after it
+    NotDecreasingTime { index: usize },
+    /// the lockouts don't follow the lockout schedule
+    InvalidLockout,
+    /// a vote's branch is missing or doesn't descend from the older vote's branch
+    InvalidBranch { index: usize },
+    /// the tower would be full, a full tower always pops its oldest vote
+    TooManyVotes { len: usize, max_size: usize },
+}
+
+/// reason a tower cannot be rebuilt from a proof
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CatchupError {
+    /// the lockouts don't follow the tower's lockout schedule
+    InvalidSchedule,
+    /// a vote's branch or the rooted branch is not in the tree
+    BranchNotFound { id: usize },
+    /// the rebuilt tower fails `verify_invariants`
+    InvariantViolation,
+}
+
+/// reason the tower cannot be moved to a new root
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RebaseError {
+    /// the new root doesn't descend from the rooted trunk
+    NotDescendant,
+}
+
+/// BFT safety condition a vote would break
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BftViolation {
+    /// a vote that is still locked out at the new vote's time is on a conflicting branch
+    ConflictingCommit {
+        committed_branch: usize,
+        committed_lockout: usize,
+    },
+    /// the newest vote's lockout expires before the new vote's time, so the new vote
+    /// doesn't build on the tower's commitments
+    ExpiresBeforeNewVote,
+}
+
+/// reason a compact encoding cannot be decoded into a tower
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    /// the input ended in the middle of a value
+    Truncated,
+    /// the varint at `offset` doesn't fit in a usize
+    InvalidVarint { offset: usize },
+    /// the tower would be full or past full
+    TooManyVotes { len: usize, max_size: usize },
+    /// there are bytes left after the last vote
+    TrailingBytes { offset: usize },
+    /// the vote at `index` is newer than the vote before it
+    NotDecreasingTime { index: usize },
+    /// the lockouts don't follow the lockout schedule
+    InvalidLockout,
+}
+
+/// reason the tower cannot be resized
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ResizeError {
+    /// the tower has more votes than the new size
+    WouldTruncate { current_votes: usize },
+    /// a tower needs room for at least one vote
+    ZeroSize,
+}
+
+/// what happened to the votes passed to `apply_votes_from_gossip`
+#[derive(Clone, Default, Debug, PartialEq, Eq)]
+pub struct GossipApplyResult {
+    pub applied: usize,
+    pub rejected_branch: usize,
+    pub rejected_convergence: usize,
+    /// the fork switch cooldown held the vote back, it can be valid later
+    pub rejected_cooldown: usize,
+    /// the tower has already voted past the vote's time
+    pub rejected_old: usize,
+    /// the vote was already applied
+    pub deduplicated: usize,
+}
+
+/// the votes of every validator for one slot
+#[derive(Clone, Debug, Default)]
+pub struct NetworkVoteRound {
+    /// `(validator id, vote)` in the order they are applied
+    pub votes: Vec<(u64, Vote)>,
+    /// convergence of the network before the round
+    pub converge_map: ConvergeMap,
+}
+
+/// what happened to the votes of a `NetworkVoteRound`
+#[derive(Clone, Debug, Default)]
+pub struct IngestResult {
+    pub votes_applied: usize,
+    pub votes_rejected_branch: usize,
+    pub votes_rejected_convergence: usize,
+    /// the fork switch cooldown held the vote back
+    pub votes_rejected_cooldown: usize,
+    /// the tower has already voted at or past the vote's time
+    pub votes_rejected_old: usize,
+    /// the tower's last branch after the round
+    pub tip: Branch,
+}
+
+/// time spent in each phase of `push_vote_timed`, all zero unless timing is enabled
+/// the duplicate, old vote and cooldown checks are counted in `validity`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct VoteTimings {
+    pub rollback: Duration,
+    pub validity: Duration,
+    pub convergence: Duration,
+    pub enter_vote: Duration,
+    /// the whole call, including rejecting the vote
+    pub total: Duration,
+}
+
+/// measures the time between calls to `lap`, does nothing when disabled
+struct PhaseTimer(Option<Instant>);
+
+impl PhaseTimer {
+    fn start(enabled: bool) -> Self {
+        PhaseTimer(if enabled { Some(Instant::now()) } else { None })
+    }
+    fn lap(&mut self) -> Duration {
+        match self.0 {
+            Some(start) => {
+                let now = Instant::now();
+                self.0 = Some(now);
+                now - start
+            }
+            None => Duration::default(),
+        }
+    }
+}
+
+/// what a stalled validator does when the network is not converged
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConvergenceFallback {
+    /// keep waiting for the network to converge
+    NeverFallback,
+    /// vote without checking convergence once it has stalled for this many rounds
+    FallbackAfterRounds(usize),
+    /// for the first `rounds` stalled rounds, the threshold vote only needs more than
+    /// `lower_pct` of the network instead of a majority
+    TemporaryThresholdLower { rounds: usize, lower_pct: usize },
+}
+
+/// how `push_vote_checked` checks convergence
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ConvergenceCheck {
+    /// `is_converged`
+    Majority,
+    /// more than this percent of the network
+    Above(usize),
+    Skip,
+}
+
+/// whether the tower is still building up its lockouts
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TowerPhase {
+    /// the tower has never been full
+    Warmup,
+    /// the tower has reached `max_size` at least once
+    SteadyState,
+}
+
+/// something that changed the tower
+#[derive(Clone, Debug)]
+pub enum TowerEvent {
+    VoteAccepted(Vote),
+    VoteRejected {
+        vote: Vote,
+        reason: VoteError,
+    },
+    /// the rolled back votes, oldest first
+    VotesRolledBack(Vec<Vote>),
+    BranchRooted(Branch),
+    /// the pruned votes that were still locked out, oldest first
+    PrunedWhileActive(Vec<Vote>),
+}
+
+/// the events of a tower in the order they happened
+#[derive(Clone, Default, Debug)]
+pub struct TowerAuditLog {
+    pub events: Vec<TowerEvent>,
+}
+
+/// votes of two towers matched by `(time, branch id)`, newest first
+#[derive(Debug)]
+pub struct TowerDiff<'a> {
+    /// the votes in both towers, as they are in the first tower
+    pub common_votes: Vec<&'a Vote>,
+    pub only_in_a: Vec<&'a Vote>,
+    pub only_in_b: Vec<&'a Vote>,
+}
+
+/// one vote of a `TowerDump`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TowerDumpRow {
+    pub time: usize,
+    pub branch: usize,
+    /// `Branch::depth` of the vote's branch
+    pub depth: usize,
+    pub age: usize,
+    pub lockout: usize,
+    /// slots left in the lockout including the tower's grace slots, 0 once it has expired
+    pub remaining: usize,
+    pub expired: bool,
 }
 
-#[derive(Debug)]
-pub struct LockTower {
-    votes: VecDeque<Vote>,
-    max_size: usize,
-    branch_trunk: Branch,
-}
+/// snapshot of the votes of a tower at `current_time`, newest first
+/// `Display` prints it as a table
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TowerDump {
+    pub current_time: usize,
+    pub branch_trunk: usize,
+    pub rows: Vec<TowerDumpRow>,
+}
+
+impl TowerDump {
+    pub fn to_json(&self) -> String {
+        let rows: Vec<_> = self
+            .rows
+            .iter()
+            .map(|r| {
+                format!(
+                    "{{\"time\":{},\"branch\":{},\"depth\":{},\"age\":{},\"lockout\":{},\"remaining\":{},\"expired\":{}}}",
+                    r.time, r.branch, r.depth, r.age, r.lockout, r.remaining, r.expired
+                )
+            }).collect();
+        format!(
+            "{{\"current_time\":{},\"branch_trunk\":{},\"votes\":[{}]}}",
+            self.current_time,
+            self.branch_trunk,
+            rows.join(",")
+        )
+    }
+}
+
+impl fmt::Display for TowerDump {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "time {} trunk {} votes {}",
+            self.current_time,
+            self.branch_trunk,
+            self.rows.len()
+        )?;
+        writeln!(
+            f,
+            "{:>6} {:>6} {:>5} {:>5} {:>7} {:>9} {:>7}",
+            "time", "branch", "depth", "age", "lockout", "remaining", "status"
+        )?;
+        for r in &self.rows {
+            let status = if r.expired { "expired" } else { "active" };
+            writeln!(
+                f,
+                "{:>6} {:>6} {:>5} {:>5} {:>7} {:>9} {:>7}",
+                r.time, r.branch, r.depth, r.age, r.lockout, r.remaining, status
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// combined view of the votes of several towers
+#[derive(Clone, Debug, Default)]
+pub struct MergedTowerView {
+    /// the most common last branch, ties go to the deeper branch
+    pub tip: Option<Branch>,
+    /// number of towers whose last branch is `tip`
+    pub tip_count: usize,
+    /// number of towers that were merged
+    pub tower_count: usize,
+    /// the votes of all the towers matched by `(time, branch id)`, newest first
+    pub votes: Vec<Vote>,
+    /// map of `time` to a map of `branch id` to the number of towers that voted for it
+    pub slot_counts: HashMap<usize, HashMap<usize, usize>>,
+}
+
+impl MergedTowerView {
+    /// fraction of the towers whose last branch is `tip`, 0.0 for an empty view
+    pub fn confidence(&self) -> f64 {
+        if self.tower_count == 0 {
+            return 0.0;
+        }
+        self.tip_count as f64 / self.tower_count as f64
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct LockTower {
+    votes: VecDeque<Vote>,
+    max_size: usize,
+    branch_trunk: Branch,
+    /// lockout a vote needs to reach before it is at the threshold depth
+    threshold_lockout: usize,
+    /// extra time after the lock height before a vote is rolled back
+    grace_slots: usize,
+    /// minimum time between two fork switches
+    fork_switch_cooldown: usize,
+    /// time of the last vote that wasn't descendent from the previous vote
+    last_fork_switch_time: Option<usize>,
+    /// `(branch id, time)` of the votes applied from gossip
+    gossip_seen: HashSet<(usize, usize)>,
+    /// time of every vote entered into the tower, times up to the last rooted vote, or
+    /// before the last prune, are dropped
+    seen_times: HashSet<usize>,
+    /// `(existing vote, attempted vote)` of the rejected double votes by time, while the
+    /// existing vote is in the tower
+    double_votes: HashMap<usize, (Vote, Vote)>,
+    phase: TowerPhase,
+    /// `None` unless `enable_audit_log` was called
+    audit_log: Option<TowerAuditLog>,
+    schedule: Box<dyn LockoutSchedule>,
+    /// votes that `push_vote` rejected only because they were not converged, oldest first
+    /// one per time, newer than the last vote and at most `MAX_PENDING_VOTES`
+    pending_votes: VecDeque<Vote>,
+    /// `(time, branch)` of every vote popped from the full tower, oldest first
+    roots: Vec<(usize, Branch)>,
+    /// keep `roots` when the tower is reset
+    preserve_history: bool,
+    /// votes entered over the tower's lifetime, not cleared by a reset
+    total_votes_cast: usize,
+    /// votes removed by a rollback over the tower's lifetime, not cleared by a reset
+    total_votes_rolled_back: usize,
+    /// measure `VoteTimings` in `push_vote_timed`
+    timing_enabled: bool,
+    /// incremented for every vote entered into the tower, not cleared by a reset
+    vote_sequence: u64,
+    /// map of `time` to the `vote_sequence` of the vote entered at that time
+    /// times up to the last rooted vote, or before the last prune, are dropped
+    sequence_at_time: HashMap<usize, u64>,
+}
+
+impl LockTower {
+    pub fn new(max_size: usize) -> Self {
+        Self {
+            votes: VecDeque::new(),
+            max_size,
+            branch_trunk: Branch::default(),
+            threshold_lockout: 1 << 8,
+            grace_slots: 0,
+            fork_switch_cooldown: 0,
+            last_fork_switch_time: None,
+            gossip_seen: HashSet::new(),
+            seen_times: HashSet::new(),
+            double_votes: HashMap::new(),
+            phase: TowerPhase::Warmup,
+            audit_log: None,
+            schedule: Box::new(DoublingSchedule),
+            pending_votes: VecDeque::new(),
+            roots: vec![],
+            preserve_history: false,
+            total_votes_cast: 0,
+            total_votes_rolled_back: 0,
+            timing_enabled: false,
+            vote_sequence: 0,
+            sequence_at_time: HashMap::new(),
+        }
+    }
+    pub fn set_threshold_lockout(&mut self, threshold_lockout: usize) {
+        self.threshold_lockout = threshold_lockout;
+    }
+    /// delay rollback of expired votes to tolerate clock skew between voters
+    pub fn set_grace_slots(&mut self, grace_slots: usize) {
+        self.grace_slots = grace_slots;
+    }
+    /// change how the lockouts grow, the votes already in the tower keep their lockouts
+    pub fn set_lockout_schedule(&mut self, schedule: Box<dyn LockoutSchedule>) {
+        self.schedule = schedule;
+    }
+    /// limit how often the tower can switch forks
+    pub fn set_fork_switch_cooldown(&mut self, fork_switch_cooldown: usize) {
+        self.fork_switch_cooldown = fork_switch_cooldown;
+    }
+    /// change `max_size` without dropping votes
+    /// if the tower is full at the new size its oldest vote is rooted
+    pub fn resize(&mut self, new_max_size: usize) -> Result<(), ResizeError> {
+        if new_max_size == 0 {
+            return Err(ResizeError::ZeroSize);
+        }
+        if new_max_size < self.votes.len() {
+            return Err(ResizeError::WouldTruncate {
+                current_votes: self.votes.len(),
+            });
+        }
+        self.max_size = new_max_size;
+        while self.is_full() {
+            self.pop_full();
+        }
+        Ok(())
+    }
+    /// measure how long each phase of `push_vote_timed` takes
+    pub fn set_timing_enabled(&mut self, timing_enabled: bool) {
+        self.timing_enabled = timing_enabled;
+    }
+    /// start recording the tower events, the log is kept until it is taken
+    pub fn enable_audit_log(&mut self) {
+        if self.audit_log.is_none() {
+            self.audit_log = Some(TowerAuditLog::default());
+        }
+    }
+    /// the events recorded so far, this disables the audit log
+    pub fn take_audit_log(&mut self) -> Option<TowerAuditLog> {
+        self.audit_log.take()
+    }
+    fn log<F: FnOnce() -> TowerEvent>(&mut self, event: F) {
+        if let Some(log) = self.audit_log.as_mut() {
+            log.events.push(event());
+        }
+    }
+    /// number of votes that entered the tower, including expired and rooted votes
+    pub fn total_votes_cast(&self) -> usize {
+        self.total_votes_cast
+    }
+    /// number of votes removed from the tower because they expired or led up to an expired vote
+    ///
+    /// while votes only leave the tower by rolling back or rooting, `votes.len()` is
+    /// `total_votes_cast - total_votes_rolled_back - commit_history().len()`, the votes dropped
+    /// by `reset`, `prune_votes_before`, `rebase` and `catchup_from_proof` are not counted
+    pub fn total_votes_rolled_back(&self) -> usize {
+        self.total_votes_rolled_back
+    }
+    /// sequence number of the newest vote entered into the tower, 0 before the first vote
+    /// it never decreases, even across rollbacks and resets
+    pub fn vote_sequence(&self) -> u64 {
+        self.vote_sequence
+    }
+    /// `vote_sequence` of the vote entered at `time`, even if it has since been rolled back
+    /// `None` once the vote at `time` or a newer vote is rooted, or `time` is pruned
+    pub fn sequence_at_time(&self, time: usize) -> Option<u64> {
+        self.sequence_at_time.get(&time).cloned()
+    }
+    /// keep `commit_history` and `finalized_branches` when the tower is reset
+    pub fn set_preserve_history(&mut self, preserve_history: bool) {
+        self.preserve_history = preserve_history;
+    }
+    /// `(time, branch)` of every vote that was rooted by popping it from the full tower,
+    /// oldest first
+    pub fn commit_history(&self) -> &[(usize, Branch)] {
+        &self.roots
+    }
+    /// time of the most recently rooted vote, from `commit_history`
+    pub fn last_rooted_slot(&self) -> Option<usize> {
+        self.roots.last().map(|(time, _)| *time)
+    }
+    /// branch of the most recently rooted vote, from `commit_history`
+    /// it stays the same when `rebase` or `catchup_from_proof` move `branch_trunk`
+    pub fn last_rooted_branch(&self) -> Option<&Branch> {
+        self.roots.last().map(|(_, branch)| branch)
+    }
+    /// slots from the time of the last rooted vote to `current_slot`
+    /// `None` if the tower has never rooted a vote, or rooted it after `current_slot`
+    pub fn finality_lag(&self, current_slot: usize) -> Option<usize> {
+        let (time, _) = self.roots.last()?;
+        current_slot.checked_sub(*time)
+    }
+    /// the last root is more than `max_lag` slots old, a tower that never rooted isn't lagging
+    pub fn is_lagging(&self, current_slot: usize, max_lag: usize) -> bool {
+        self.finality_lag(current_slot)
+            .map(|lag| lag > max_lag)
+            .unwrap_or(false)
+    }
+    /// clear all the votes, the configuration is preserved
+    pub fn reset(&mut self) {
+        self.reset_to(Branch::default());
+    }
+    /// clear all the votes and start over from `root`
+    pub fn reset_to(&mut self, root: Branch) {
+        self.votes.clear();
+        self.gossip_seen.clear();
+        self.seen_times.clear();
+        self.double_votes.clear();
+        self.pending_votes.clear();
+        self.sequence_at_time.clear();
+        self.last_fork_switch_time = None;
+        if !self.preserve_history {
+            self.roots.clear();
+        }
+        self.phase = TowerPhase::Warmup;
+        self.branch_trunk = root;
+    }
+    /// move the rooted trunk forward to `new_root`, which was rooted by the network
+    /// the votes for `new_root` and its ancestors are removed, and so are the votes
+    /// that don't descend from it since they can never be rooted
+    pub fn rebase(
+        &mut self,
+        new_root: Branch,
+        branch_tree: &BranchTree,
+    ) -> Result<(), RebaseError> {
+        if !new_root.is_descendant_of(&self.branch_trunk, branch_tree) {
+            return Err(RebaseError::NotDescendant);
+        }
+        self.votes.retain(|v| {
+            v.branch.id != new_root.id && v.branch.is_descendant_of(&new_root, branch_tree)
+        });
+        self.branch_trunk = new_root;
+        Ok(())
+    }
+    /// remove the votes with `time < slot` regardless of their lockouts
+    /// returns the number of removed votes
+    pub fn prune_votes_before(&mut self, slot: usize) -> usize {
+        let mut pruned = vec![];
+        while self.first_vote().map(|v| v.time < slot).unwrap_or(false) {
+            pruned.push(self.votes.pop_back().unwrap());
+        }
+        let count = pruned.len();
+        self.prune_removed_votes();
+        self.sequence_at_time.retain(|time, _| *time >= slot);
+        self.seen_times.retain(|time| *time >= slot);
+        pruned.retain(|v| self.is_vote_active(v, slot));
+        if !pruned.is_empty() {
+            self.log(|| TowerEvent::PrunedWhileActive(pruned));
+        }
+        count
+    }
+    /// * depth - depth of the vote that must be converged, 0 uses `threshold_depth`
+    pub fn push_vote(
+        &mut self,
+        vote: Vote,
+        branch_tree: &BranchTree,
+        converge_map: &ConvergeMap,
+        depth: usize,
+    ) -> bool {
+        self.try_push_vote(vote, branch_tree, converge_map, depth)
+            .is_ok()
+    }
+    /// `push_vote` that returns the reason the vote was rejected
+    pub fn try_push_vote(
+        &mut self,
+        vote: Vote,
+        branch_tree: &BranchTree,
+        converge_map: &ConvergeMap,
+        depth: usize,
+    ) -> Result<(), VoteError> {
+        self.push_vote_timed(vote, branch_tree, converge_map, depth)
+            .0
+    }
+    /// `try_push_vote` that also returns how long each phase took
+    /// the timings are only measured if `set_timing_enabled` is set
+    pub fn push_vote_timed(
+        &mut self,
+        vote: Vote,
+        branch_tree: &BranchTree,
+        converge_map: &ConvergeMap,
+        depth: usize,
+    ) -> (Result<(), VoteError>, VoteTimings) {
+        let mut total = PhaseTimer::start(self.timing_enabled);
+        let mut timings = VoteTimings::default();
+        let result = self.push_vote_logged(
+            vote,
+            branch_tree,
+            converge_map,
+            depth,
+            ConvergenceCheck::Majority,
+            &mut timings,
+        );
+        timings.total = total.lap();
+        (result, timings)
+    }
+    /// `push_vote_checked` that logs the rejected votes to the audit log
+    /// every push goes through here, so this is where the push is traced
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "push_vote",
+            level = "debug",
+            skip_all,
+            fields(branch = vote.branch.id, time = vote.time)
+        )
+    )]
+    fn push_vote_logged(
+        &mut self,
+        vote: Vote,
+        branch_tree: &BranchTree,
+        converge_map: &ConvergeMap,
+        depth: usize,
+        convergence: ConvergenceCheck,
+        timings: &mut VoteTimings,
+    ) -> Result<(), VoteError> {
+        let rejected = self.audit_log.as_ref().map(|_| vote.clone());
+        let result =
+            self.push_vote_checked(vote, branch_tree, converge_map, depth, convergence, timings);
+        if let (Err(reason), Some(vote)) = (&result, rejected) {
+            self.log(|| TowerEvent::VoteRejected {
+                vote,
+                reason: reason.clone(),
+            });
+        }
+        #[cfg(feature = "tracing")]
+        tracing::debug!(outcome = ?result, "push_vote");
+        result
+    }
+    /// apply a batch of votes oldest first, votes at the same time go in branch id order
+    /// returns the result of each vote in the order they were passed in
+    pub fn apply_votes_ordered(
+        &mut self,
+        votes: Vec<Vote>,
+        branch_tree: &BranchTree,
+        converge_map: &ConvergeMap,
+        depth: usize,
+    ) -> Vec<Result<(), VoteError>> {
+        let mut order: Vec<(usize, Vote)> = votes.into_iter().enumerate().collect();
+        order.sort_by_key(|(_, v)| (v.time, v.branch.id));
+        let mut results: Vec<Option<Result<(), VoteError>>> = vec![None; order.len()];
+        for (ix, vote) in order {
+            results[ix] = Some(self.try_push_vote(vote, branch_tree, converge_map, depth));
+        }
+        results.into_iter().map(|r| r.unwrap()).collect()
+    }
+    fn push_vote_checked(
+        &mut self,
+        vote: Vote,
+        branch_tree: &BranchTree,
+        converge_map: &ConvergeMap,
+        depth: usize,
+        convergence: ConvergenceCheck,
+        timings: &mut VoteTimings,
+    ) -> Result<(), VoteError> {
+        let mut timer = PhaseTimer::start(self.timing_enabled);
+        let (checked, checks) = self.check_vote(
+            &vote,
+            branch_tree,
+            converge_map,
+            depth,
+            convergence,
+            &mut timer,
+        );
+        timings.validity = checks.validity;
+        timings.convergence = checks.convergence;
+        let fork_switch = self.is_fork_switch(&vote, branch_tree);
+        match checked {
+            // the vote's time or the cooldown rejected it, the tower stays where it is
+            Err(VoteError::DoubleVote { .. })
+            | Err(VoteError::OldVote { .. })
+            | Err(VoteError::CooldownActive { .. }) => (),
+            _ => {
+                self.rollback(vote.time);
+                timings.rollback = timer.lap();
+            }
+        }
+        match checked {
+            Ok(()) => (),
+            Err(VoteError::DoubleVote { time }) => {
+                // the same vote pushed again is not an equivocation
+                let existing = self
+                    .votes
+                    .iter()
+                    .find(|v| v.time == time && v.branch.id != vote.branch.id)
+                    .cloned();
+                if let Some(existing) = existing {
+                    self.double_votes.entry(time).or_insert((existing, vote));
+                }
+                return checked;
+            }
+            Err(VoteError::NotConverged) => {
+                self.queue_pending(vote);
+                return checked;
+            }
+            Err(_) => return checked,
+        }
+        if fork_switch {
+            self.last_fork_switch_time = Some(vote.time);
+        }
+        self.enter_vote(vote);
+        if self.is_full() {
+            self.pop_full();
+        }
+        timings.enter_vote = timer.lap();
+        Ok(())
+    }
+    /// fast-forward a stale tower with `recent_votes`, oldest first, during recovery
+    /// the votes are already finalized so convergence is not checked, the lockouts
+    /// are rebuilt as if the votes were pushed one by one
+    /// stops at the first vote that doesn't descend from the previous one or is out of order
+    /// returns the number of votes that were applied
+    pub fn catchup(&mut self, recent_votes: &[Vote], branch_tree: &BranchTree) -> usize {
+        let mut applied = 0;
+        for vote in recent_votes {
+            let mut vote = vote.clone();
+            vote.lockout = 2;
+            let old = self
+                .last_vote()
+                .map(|v| v.time > vote.time)
+                .unwrap_or(false);
+            if old || self.seen_times.contains(&vote.time) {
+                break;
+            }
+            self.rollback(vote.time);
+            if !self.is_valid(&vote, branch_tree) {
+                break;
+            }
+            self.enter_vote(vote);
+            if self.is_full() {
+                self.pop_full();
+            }
+            applied += 1;
+        }
+        applied
+    }
+    /// replace the votes and the rooted trunk with the ones in `proof`, when restoring
+    /// from a snapshot, the tower is unchanged on failure
+    pub fn catchup_from_proof(
+        &mut self,
+        proof: &TowerProof,
+        branch_tree: &BranchTree,
+    ) -> Result<(), CatchupError> {
+        let lockouts = proof.votes.iter().map(|(_, lockout)| *lockout);
+        if !follows_schedule(&*self.schedule, lockouts) {
+            return Err(CatchupError::InvalidSchedule);
+        }
+        if proof.times.len() != proof.votes.len() {
+            return Err(CatchupError::InvariantViolation);
+        }
+        let find = |id| {
+            if id == 0 {
+                Ok(Branch::default())
+            } else {
+                branch_tree
+                    .get(id)
+                    .cloned()
+                    .ok_or(CatchupError::BranchNotFound { id })
+            }
+        };
+        let branch_trunk = find(proof.branch_trunk)?;
+        let mut votes = VecDeque::new();
+        for ((id, lockout), time) in proof.votes.iter().zip(&proof.times) {
+            votes.push_back(Vote {
+                branch: find(*id)?,
+                time: *time,
+                lockout: *lockout,
+                memo: None,
+            });
+        }
+        let old_votes = std::mem::replace(&mut self.votes, votes);
+        let old_trunk = std::mem::replace(&mut self.branch_trunk, branch_trunk);
+        if !self.verify_invariants(branch_tree) {
+            self.votes = old_votes;
+            self.branch_trunk = old_trunk;
+            return Err(CatchupError::InvariantViolation);
+        }
+        // nothing the discarded tower voted on applies to the restored one
+        let votes = std::mem::take(&mut self.votes);
+        let branch_trunk = self.branch_trunk.clone();
+        self.reset_to(branch_trunk);
+        self.votes = votes;
+        self.seen_times = proof.times.iter().cloned().collect();
+        Ok(())
+    }
+    /// check the invariants that pushing votes maintains
+    /// * the tower is never full
+    /// * lockouts increase with depth
+    /// * times don't increase with depth
+    /// * every vote descends from the vote before it, and the oldest from the rooted trunk
+    pub fn verify_invariants(&self, branch_tree: &BranchTree) -> bool {
+        if self.votes.len() >= self.max_size {
+            return false;
+        }
+        let mut older = self.branch_trunk.clone();
+        let mut ordered = true;
+        for (i, vote) in self.votes.iter().enumerate().rev() {
+            if let Some(newer) = i.checked_sub(1).map(|i| &self.votes[i]) {
+                ordered &= newer.lockout < vote.lockout && newer.time >= vote.time;
+            }
+            ordered &= older.is_trunk_of(&vote.branch, branch_tree);
+            older = vote.branch.clone();
+        }
+        ordered
+    }
+    /// up to `max_votes` votes to gossip instead of the whole tower, newest first
+    /// the first vote is picked first, then the last vote, then the votes at depths
+    /// 1, 2, 4, 8... and then the remaining votes from the newest
+    pub fn gossip_votes_subset(&self, max_votes: usize) -> Vec<&Vote> {
+        let len = self.votes.len();
+        if len == 0 {
+            return vec![];
+        }
+        let exponential = (0..).map(|k| 1 << k).take_while(|i| *i < len);
+        let mut picked: Vec<usize> = vec![];
+        for i in Some(len - 1)
+            .into_iter()
+            .chain(Some(0))
+            .chain(exponential)
+            .chain(0..len)
+        {
+            if picked.len() == max_votes {
+                break;
+            }
+            if !picked.contains(&i) {
+                picked.push(i);
+            }
+        }
+        picked.sort();
+        picked.into_iter().map(|i| &self.votes[i]).collect()
+    }
+    /// compare the votes of two towers, the lockouts are not compared
+    pub fn tower_state_diff<'a>(a: &'a LockTower, b: &'a LockTower) -> TowerDiff<'a> {
+        let key = |v: &Vote| (v.time, v.branch.id);
+        let keys_a: HashSet<_> = a.votes.iter().map(key).collect();
+        let keys_b: HashSet<_> = b.votes.iter().map(key).collect();
+        let (common_votes, only_in_a) = a.votes.iter().partition(|v| keys_b.contains(&key(v)));
+        let only_in_b = b
+            .votes
+            .iter()
+            .filter(|v| !keys_a.contains(&key(v)))
+            .collect();
+        TowerDiff {
+            common_votes,
+            only_in_a,
+            only_in_b,
+        }
+    }
+    /// table of the votes at `current_time` for debugging
+    pub fn dump_state(&self, current_time: usize, branch_tree: &BranchTree) -> TowerDump {
+        let rows = self
+            .votes
+            .iter()
+            .map(|v| {
+                let remaining = self.slots_until_inactive(v, current_time);
+                TowerDumpRow {
+                    time: v.time,
+                    branch: v.branch.id,
+                    depth: v.branch.depth(branch_tree),
+                    age: current_time.saturating_sub(v.time),
+                    lockout: v.lockout,
+                    remaining,
+                    expired: remaining == 0,
+                }
+            }).collect();
+        TowerDump {
+            current_time,
+            branch_trunk: self.branch_trunk.id,
+            rows,
+        }
+    }
+    /// merge the votes of `towers` into a read only view
+    pub fn merge_towers(towers: &[&LockTower], branch_tree: &BranchTree) -> MergedTowerView {
+        let mut view = MergedTowerView {
+            tower_count: towers.len(),
+            ..MergedTowerView::default()
+        };
+        let mut tips: HashMap<usize, (Branch, usize)> = HashMap::new();
+        let mut seen = HashSet::new();
+        for tower in towers {
+            let tip = tower.last_branch();
+            tips.entry(tip.id).or_insert((tip, 0)).1 += 1;
+            for vote in &tower.votes {
+                *view
+                    .slot_counts
+                    .entry(vote.time)
+                    .or_default()
+                    .entry(vote.branch.id)
+                    .or_insert(0) += 1;
+                if seen.insert((vote.time, vote.branch.id)) {
+                    view.votes.push(vote.clone());
+                }
+            }
+        }
+        view.votes.sort_by_key(|v| (Reverse(v.time), v.branch.id));
+        if let Some((tip, count)) = tips.into_values().max_by(|a, b| {
+            a.1.cmp(&b.1)
+                .then_with(|| branch_depth_cmp(&a.0, &b.0, branch_tree))
+                .then_with(|| b.0.id.cmp(&a.0.id))
+        }) {
+            view.tip = Some(tip);
+            view.tip_count = count;
+        }
+        view
+    }
+    /// the vote doesn't descend from the previous vote
+    fn is_fork_switch(&self, vote: &Vote, branch_tree: &BranchTree) -> bool {
+        !self.last_branch().is_trunk_of(&vote.branch, branch_tree)
+    }
+    /// time left at `time` until the next fork switch is allowed
+    fn fork_switch_cooldown_remaining(&self, time: usize) -> Option<usize> {
+        self.last_fork_switch_time
+            .map(|last| time.saturating_sub(last))
+            .filter(|elapsed| *elapsed < self.fork_switch_cooldown)
+            .map(|elapsed| self.fork_switch_cooldown - elapsed)
+    }
+    /// push a vote from a trusted ledger replay without the branch and convergence checks
+    ///
+    /// # Safety
+    ///
+    /// The caller must guarantee that the vote was accepted by `push_vote` before:
+    /// * votes are pushed in time order, and never twice for the same time
+    /// * the vote's branch descends from the last vote left after rollback
+    /// * the vote has the lockout of a new vote, 2
+    ///
+    /// Otherwise the tower ends up in a state `push_vote` can never produce.
+    pub unsafe fn push_vote_unchecked(&mut self, vote: Vote) {
+        self.rollback(vote.time);
+        self.enter_vote(vote);
+        if self.is_full() {
+            self.pop_full();
+        }
+    }
+    /// `try_push_vote` that also rejects a vote that isn't newer than the last vote
+    pub fn push_vote_strict(
+        &mut self,
+        vote: Vote,
+        branch_tree: &BranchTree,
+        converge_map: &ConvergeMap,
+        depth: usize,
+    ) -> Result<(), VoteError> {
+        if let Some(last) = self.last_vote() {
+            if vote.time <= last.time {
+                return Err(VoteError::NonMonotonicTime {
+                    expected_min: last.time + 1,
+                    got: vote.time,
+                });
+            }
+        }
+        self.try_push_vote(vote, branch_tree, converge_map, depth)
+    }
+    /// `push_vote_strict` that also rejects a vote more than `max_future_slots` ahead of
+    /// the oracle's current slot
+    pub fn push_vote_validated(
+        &mut self,
+        vote: Vote,
+        branch_tree: &BranchTree,
+        converge_map: &ConvergeMap,
+        depth: usize,
+        oracle: &dyn SlotOracle,
+        max_future_slots: usize,
+    ) -> Result<(), VoteError> {
+        let current = oracle.current_slot();
+        if vote.time > current.saturating_add(max_future_slots) {
+            return Err(VoteError::FutureVote {
+                slot: vote.time,
+                current,
+            });
+        }
+        self.push_vote_strict(vote, branch_tree, converge_map, depth)
+    }
+    /// verify the message's signature and push its vote with `try_push_vote`
+    pub fn apply_vote_message(
+        &mut self,
+        msg: &VoteMessage,
+        verifier: &dyn SignatureVerifier,
+        branch_tree: &BranchTree,
+        converge_map: &ConvergeMap,
+        depth: usize,
+    ) -> Result<(), VoteError> {
+        if !verifier.verify(&msg.signed_bytes(), &msg.signature, msg.voter_id) {
+            return Err(VoteError::InvalidSignature);
+        }
+        self.try_push_vote(msg.vote.clone(), branch_tree, converge_map, depth)
+    }
+    /// `try_push_vote` for a validator that has been stalled on convergence for
+    /// `stalled_rounds` rounds, `fallback` decides how convergence is checked
+    pub fn push_vote_with_fallback(
+        &mut self,
+        vote: Vote,
+        branch_tree: &BranchTree,
+        converge_map: &ConvergeMap,
+        depth: usize,
+        fallback: &ConvergenceFallback,
+        stalled_rounds: usize,
+    ) -> Result<(), VoteError> {
+        let convergence = match *fallback {
+            ConvergenceFallback::FallbackAfterRounds(rounds) if stalled_rounds >= rounds => {
+                ConvergenceCheck::Skip
+            }
+            ConvergenceFallback::TemporaryThresholdLower { rounds, lower_pct }
+                if stalled_rounds > 0 && stalled_rounds <= rounds =>
+            {
+                ConvergenceCheck::Above(lower_pct)
+            }
+            _ => ConvergenceCheck::Majority,
+        };
+        self.push_vote_logged(
+            vote,
+            branch_tree,
+            converge_map,
+            depth,
+            convergence,
+            &mut VoteTimings::default(),
+        )
+    }
+    /// votes that `push_vote` rejected because the network was not converged, oldest first
+    pub fn votes_not_converged(&self) -> impl Iterator<Item = &Vote> {
+        self.pending_votes.iter()
+    }
+    /// push the votes from `votes_not_converged` that are safe to vote with the new
+    /// `converge_map`, the votes that are still not converged are kept for the next retry
+    /// and the votes that are no longer valid are dropped
+    /// returns the number of votes that were pushed
+    pub fn retry_pending(
+        &mut self,
+        branch_tree: &BranchTree,
+        converge_map: &ConvergeMap,
+        depth: usize,
+    ) -> usize {
+        let mut applied = 0;
+        let pending: Vec<_> = self.pending_votes.drain(..).collect();
+        for vote in pending {
+            match self.is_safe_to_vote(&vote, branch_tree, converge_map, depth) {
+                Ok(()) => {
+                    if self
+                        .try_push_vote(vote, branch_tree, converge_map, depth)
+                        .is_ok()
+                    {
+                        applied += 1;
+                    }
+                }
+                Err(VoteError::NotConverged) => self.pending_votes.push_back(vote),
+                Err(_) => (),
+            }
+        }
+        self.prune_pending();
+        applied
+    }
+    /// queue a vote that was not converged, replacing any pending vote for the same time
+    fn queue_pending(&mut self, vote: Vote) {
+        self.pending_votes.retain(|v| v.time != vote.time);
+        self.pending_votes.push_back(vote);
+        self.prune_pending();
+    }
+    /// drop the pending votes that are no newer than the last vote, and the oldest ones
+    /// over `MAX_PENDING_VOTES`
+    fn prune_pending(&mut self) {
+        if let Some(last) = self.votes.front().map(|v| v.time) {
+            self.pending_votes.retain(|v| v.time > last);
+        }
+        while self.pending_votes.len() > MAX_PENDING_VOTES {
+            self.pending_votes.pop_front();
+        }
+    }
+    /// the vote in the tower and the rejected vote for the same `time`
+    /// only recorded while the existing vote is still in the tower
+    pub fn double_vote_proof(&self, time: usize) -> Option<(&Vote, &Vote)> {
+        self.double_votes
+            .get(&time)
+            .map(|(existing, attempted)| (existing, attempted))
+    }
+    /// check if the vote at `depth` has over 50% of the network committed
+    /// `depth` counts from the newest vote left after rolling back `expired` votes
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self, converge_map, expired), ret)
+    )]
+    fn is_converged(&self, converge_map: &ConvergeMap, expired: usize, depth: usize) -> bool {
+        self.is_converged_above(converge_map, expired, depth, 50)
+    }
+    fn is_converged_above(
+        &self,
+        converge_map: &ConvergeMap,
+        expired: usize,
+        depth: usize,
+        pct: usize,
+    ) -> bool {
+        self.get_vote(expired + depth)
+            .map(|v| {
+                let v = converge_map.get(v.branch.id);
+                // hard coded to 100 nodes
+                assert!(v <= MAX_NETWORK_SIZE);
+                v > pct
+            }).unwrap_or(true)
+    }
+
+    /// push every vote of `round` in order
+    pub fn ingest_network_votes(
+        &mut self,
+        round: &NetworkVoteRound,
+        branch_tree: &BranchTree,
+        depth: usize,
+    ) -> IngestResult {
+        let mut result = IngestResult::default();
+        for (_, vote) in &round.votes {
+            match self.try_push_vote(vote.clone(), branch_tree, &round.converge_map, depth) {
+                Ok(()) => result.votes_applied += 1,
+                Err(VoteError::NotConverged) => result.votes_rejected_convergence += 1,
+                Err(VoteError::OldVote { .. })
+                | Err(VoteError::DoubleVote { .. })
+                | Err(VoteError::NonMonotonicTime { .. })
+                | Err(VoteError::FutureVote { .. }) => result.votes_rejected_old += 1,
+                Err(VoteError::CooldownActive { .. }) => result.votes_rejected_cooldown += 1,
+                Err(VoteError::InvalidBranch)
+                | Err(VoteError::BelowFinalized)
+                | Err(VoteError::InvalidSignature) => result.votes_rejected_branch += 1,
+            }
+        }
+        result.tip = self.last_branch();
+        result
+    }
+    /// push the votes received over gossip, skipping any vote that was already applied
+    /// the gossiped lockouts are ignored, every vote enters the tower with a lockout of 2
+    pub fn apply_votes_from_gossip(
+        &mut self,
+        incoming: &[Vote],
+        branch_tree: &BranchTree,
+        converge_map: &ConvergeMap,
+        depth: usize,
+    ) -> GossipApplyResult {
+        let mut result = GossipApplyResult::default();
+        for vote in incoming {
+            let key = (vote.branch.id, vote.time);
+            if self.gossip_seen.contains(&key) {
+                result.deduplicated += 1;
+                continue;
+            }
+            let mut vote = vote.clone();
+            vote.lockout = 2;
+            match self.try_push_vote(vote, branch_tree, converge_map, depth) {
+                Ok(()) => {
+                    self.gossip_seen.insert(key);
+                    result.applied += 1;
+                }
+                Err(VoteError::CooldownActive { .. }) => result.rejected_cooldown += 1,
+                Err(VoteError::InvalidBranch)
+                | Err(VoteError::BelowFinalized)
+                | Err(VoteError::InvalidSignature) => result.rejected_branch += 1,
+                Err(VoteError::NotConverged) => result.rejected_convergence += 1,
+                Err(VoteError::OldVote { .. })
+                | Err(VoteError::DoubleVote { .. })
+                | Err(VoteError::NonMonotonicTime { .. })
+                | Err(VoteError::FutureVote { .. }) => result.rejected_old += 1,
+            }
+        }
+        result
+    }
+    pub fn tower_proof(&self) -> TowerProof {
+        TowerProof {
+            votes: self
+                .votes
+                .iter()
+                .map(|v| (v.branch.id, v.lockout))
+                .collect(),
+            times: self.votes.iter().map(|v| v.time).collect(),
+            branch_trunk: self.branch_trunk.id,
+        }
+    }
+    /// check that `vote` can be pushed on top of the tower in `proof`, assuming no rollback
+    pub fn verify_tower_proof(
+        proof: &TowerProof,
+        vote: &Vote,
+        branch_tree: &BranchTree,
+    ) -> Result<(), ProofError> {
+        Self::verify_tower_proof_with_schedule(proof, vote, branch_tree, &DoublingSchedule)
+    }
+    /// `verify_tower_proof` for a tower whose lockouts grow with `schedule`
+    pub fn verify_tower_proof_with_schedule(
+        proof: &TowerProof,
+        vote: &Vote,
+        branch_tree: &BranchTree,
+        schedule: &dyn LockoutSchedule,
+    ) -> Result<(), ProofError> {
+        if !follows_schedule(schedule, proof.votes.iter().map(|(_, lockout)| *lockout)) {
+            return Err(ProofError::InvalidLockout);
+        }
+        let last_id = proof
+            .votes
+            .first()
+            .map(|(id, _)| *id)
+            .unwrap_or(proof.branch_trunk);
+        let last_branch = Branch {
+            id: last_id,
+            base: 0,
+        };
+        if !vote.branch.is_descendant_of(&last_branch, branch_tree) {
+            return Err(ProofError::InvalidBranch);
+        }
+        Ok(())
+    }
+    /// `(time, branch id, lockout)` of each vote, newest first
+    pub fn export_votes(&self) -> Vec<(usize, usize, usize)> {
+        self.votes
+            .iter()
+            .map(|v| (v.time, v.branch.id, v.lockout))
+            .collect()
+    }
+    /// rebuild a tower from `export_votes` on top of the rooted `branch_trunk_id`
+    /// the branches are looked up in `branch_tree` and must form a chain back to the trunk
+    pub fn import_votes(
+        exported: &[(usize, usize, usize)],
+        branch_trunk_id: usize,
+        max_size: usize,
+        branch_tree: &BranchTree,
+    ) -> Result<LockTower, ImportError> {
+        let schedule = Box::new(DoublingSchedule);
+        Self::import_votes_with_schedule(exported, branch_trunk_id, max_size, branch_tree, schedule)
+    }
+    /// `import_votes` for a tower whose lockouts grow with `schedule`, the imported tower
+    /// keeps using it
+    pub fn import_votes_with_schedule(
+        exported: &[(usize, usize, usize)],
+        branch_trunk_id: usize,
+        max_size: usize,
+        branch_tree: &BranchTree,
+        schedule: Box<dyn LockoutSchedule>,
+    ) -> Result<LockTower, ImportError> {
+        if exported.len() >= max_size {
+            return Err(ImportError::TooManyVotes {
+                len: exported.len(),
+                max_size,
+            });
+        }
+        for (index, pair) in exported.windows(2).enumerate() {
+            if pair[0].0 < pair[1].0 {
+                return Err(ImportError::NotDecreasingTime { index: index + 1 });
+            }
+        }
+        if !follows_schedule(&*schedule, exported.iter().map(|(_, _, lockout)| *lockout)) {
+            return Err(ImportError::InvalidLockout);
+        }
+        let find = |id| {
+            if id == 0 {
+                Some(Branch::default())
+            } else {
+                branch_tree.get(id).cloned()
+            }
+        };
+        let branch_trunk = find(branch_trunk_id).ok_or(ImportError::InvalidBranch {
+            index: exported.len(),
+        })?;
+        let mut tower = LockTower::new(max_size);
+        tower.set_lockout_schedule(schedule);
+        tower.branch_trunk = branch_trunk;
+        // walk from the oldest vote so each branch is checked against the one before it
+        for (index, (time, id, lockout)) in exported.iter().enumerate().rev() {
+            let branch = find(*id)
+                .filter(|b| b.is_descendant_of(&tower.last_branch(), branch_tree))
+                .ok_or(ImportError::InvalidBranch { index })?;
+            tower.seen_times.insert(*time);
+            tower.votes.push_front(Vote {
+                branch,
+                time: *time,
+                lockout: *lockout,
+                memo: None,
+            });
+        }
+        Ok(tower)
+    }
+    /// varint encoding of the rooted branch and every vote, newest first
+    /// each branch is written as its id and base id, the branch tree is not included
+    pub fn to_compact_encoding(&self) -> Vec<u8> {
+        let mut out = vec![];
+        write_varint(&mut out, self.branch_trunk.id);
+        write_varint(&mut out, self.branch_trunk.base);
+        write_varint(&mut out, self.votes.len());
+        for vote in &self.votes {
+            write_varint(&mut out, vote.branch.id);
+            write_varint(&mut out, vote.branch.base);
+            write_varint(&mut out, vote.time);
+            write_varint(&mut out, vote.lockout);
+        }
+        out
+    }
+    /// rebuild a tower written by `to_compact_encoding`
+    pub fn from_compact_encoding(bytes: &[u8], max_size: usize) -> Result<LockTower, DecodeError> {
+        let mut pos = 0;
+        let mut read = || read_varint(bytes, &mut pos);
+        let branch_trunk = Branch::new(read()?, read()?);
+        let len = read()?;
+        if len >= max_size {
+            return Err(DecodeError::TooManyVotes { len, max_size });
+        }
+        let mut tower = LockTower::new(max_size);
+        tower.branch_trunk = branch_trunk;
+        for _ in 0..len {
+            let branch = Branch::new(read()?, read()?);
+            let time = read()?;
+            let lockout = read()?;
+            tower.seen_times.insert(time);
+            tower.votes.push_back(Vote {
+                branch,
+                time,
+                lockout,
+                memo: None,
+            });
+        }
+        if pos != bytes.len() {
+            return Err(DecodeError::TrailingBytes { offset: pos });
+        }
+        for index in 1..tower.votes.len() {
+            if tower.votes[index - 1].time < tower.votes[index].time {
+                return Err(DecodeError::NotDecreasingTime { index });
+            }
+        }
+        if !follows_schedule(&*tower.schedule, tower.votes.iter().map(|v| v.lockout)) {
+            return Err(DecodeError::InvalidLockout);
+        }
+        Ok(tower)
+    }
+    /// check `vote` against the BFT safety conditions, separately from the lockout rules
+    /// a conflicting commitment is reported for the vote with the largest lockout
+    pub fn apply_bft_rules(
+        &self,
+        vote: &Vote,
+        branch_tree: &BranchTree,
+    ) -> Result<(), BftViolation> {
+        let expired = self.expired_count(vote.time);
+        let conflict = self
+            .votes
+            .iter()
+            .skip(expired)
+            .filter(|v| !v.is_trunk_of(vote, branch_tree))
+            .max_by_key(|v| v.lockout);
+        if let Some(v) = conflict {
+            return Err(BftViolation::ConflictingCommit {
+                committed_branch: v.branch.id,
+                committed_lockout: v.lockout,
+            });
+        }
+        if expired > 0 {
+            return Err(BftViolation::ExpiresBeforeNewVote);
+        }
+        Ok(())
+    }
+    /// run all the checks `push_vote` would do, without modifying the tower
+    /// returns the first failing reason
+    pub fn is_safe_to_vote(
+        &self,
+        vote: &Vote,
+        branch_tree: &BranchTree,
+        converge_map: &ConvergeMap,
+        depth: usize,
+    ) -> Result<(), VoteError> {
+        let mut timer = PhaseTimer::start(false);
+        let convergence = ConvergenceCheck::Majority;
+        let (checked, _) = self.check_vote(
+            vote,
+            branch_tree,
+            converge_map,
+            depth,
+            convergence,
+            &mut timer,
+        );
+        checked
+    }
+    /// the checks of `push_vote` in the order they are reported, on the tower as it
+    /// would be after rolling back to the vote's time
+    /// * `OldVote`, `DoubleVote` - the vote's time
+    /// * `BelowFinalized`, `CooldownActive`, `InvalidBranch` - the vote's branch
+    /// * `NotConverged` - the network
+    ///
+    /// only the `validity` and `convergence` timings are measured
+    fn check_vote(
+        &self,
+        vote: &Vote,
+        branch_tree: &BranchTree,
+        converge_map: &ConvergeMap,
+        depth: usize,
+        convergence: ConvergenceCheck,
+        timer: &mut PhaseTimer,
+    ) -> (Result<(), VoteError>, VoteTimings) {
+        let mut timings = VoteTimings::default();
+        // the votes that would remain after rollback start at `expired`
+        let expired = self.expired_count(vote.time);
+        let last = self.votes.get(expired);
+        let valid = if let Some(last) = last.filter(|last| last.time > vote.time) {
+            Err(VoteError::OldVote {
+                last_time: last.time,
+                vote_time: vote.time,
+            })
+        } else if self.seen_times.contains(&vote.time) {
+            Err(VoteError::DoubleVote { time: vote.time })
+        } else if !branch_tree.is_finalized_trunk_of(&vote.branch) {
+            Err(VoteError::BelowFinalized)
+        } else if let Some(remaining) = self
+            .fork_switch_cooldown_remaining(vote.time)
+            .filter(|_| self.is_fork_switch(vote, branch_tree))
+        {
+            Err(VoteError::CooldownActive { remaining })
+        } else {
+            let last_branch = last
+                .map(|v| v.branch.clone())
+                .unwrap_or_else(|| self.branch_trunk.clone());
+            if vote.branch.is_descendant_of(&last_branch, branch_tree) {
+                Ok(())
+            } else {
+                Err(VoteError::InvalidBranch)
+            }
+        };
+        timings.validity = timer.lap();
+        if valid.is_err() {
+            return (valid, timings);
+        }
+        let depth = if depth == 0 {
+            self.threshold_depth_after(expired)
+        } else {
+            depth
+        };
+        let converged = match convergence {
+            ConvergenceCheck::Majority => self.is_converged(converge_map, expired, depth),
+            ConvergenceCheck::Above(pct) => {
+                self.is_converged_above(converge_map, expired, depth, pct)
+            }
+            ConvergenceCheck::Skip => true,
+        };
+        timings.convergence = timer.lap();
+        if !converged {
+            return (Err(VoteError::NotConverged), timings);
+        }
+        (Ok(()), timings)
+    }
+
+    /// position of the oldest vote whose lockout has reached the threshold lockout
+    /// if no vote has reached it the depth is past the end of the tower
+    pub fn threshold_depth(&self) -> usize {
+        self.threshold_depth_after(0)
+    }
+    /// `threshold_depth` of the tower with the first `expired` votes removed
+    fn threshold_depth_after(&self, expired: usize) -> usize {
+        let threshold_lockout = self.threshold_lockout;
+        self.votes
+            .iter()
+            .skip(expired)
+            .rposition(|v| v.lockout >= threshold_lockout)
+            .unwrap_or(self.votes.len() - expired)
+    }
+    /// number of slots from `current_time` until the tower can vote for `candidate`
+    /// returns `None` if the tower is already free to vote for it
+    /// a candidate that doesn't descend from the rooted trunk is never unlocked, `Some(usize::MAX)`
+    pub fn slots_until_unlock(
+        &self,
+        candidate: &Branch,
+        branch_tree: &BranchTree,
+        current_time: usize,
+    ) -> Option<usize> {
+        if !candidate.is_descendant_of(&self.branch_trunk, branch_tree) {
+            return Some(usize::MAX);
+        }
+        // the deepest vote that is not an ancestor of the candidate has to expire
+        self.votes
+            .iter()
+            .rposition(|v| !v.branch.is_trunk_of(candidate, branch_tree))
+            .map(|i| self.votes[i].lock_height() + self.grace_slots + 1)
+            .and_then(|unlock| unlock.checked_sub(current_time))
+            .filter(|remaining| *remaining > 0)
+    }
+    /// check `vote` without pushing it, a vote that is locked out reports how long to wait
+    /// the wait is until the deepest conflicting lockout expires, or the fork switch
+    /// cooldown ends if that is later
+    pub fn push_vote_or_wait(
+        &self,
+        vote: &Vote,
+        branch_tree: &BranchTree,
+        converge_map: &ConvergeMap,
+        depth: usize,
+    ) -> VotePushDecision {
+        let mut timer = PhaseTimer::start(false);
+        let convergence = ConvergenceCheck::Majority;
+        let (checked, _) = self.check_vote(
+            vote,
+            branch_tree,
+            converge_map,
+            depth,
+            convergence,
+            &mut timer,
+        );
+        let unlock = || self.slots_until_unlock(&vote.branch, branch_tree, vote.time);
+        match checked {
+            Ok(()) => VotePushDecision::VoteNow,
+            Err(VoteError::NotConverged) => VotePushDecision::NotConverged,
+            Err(VoteError::OldVote { .. })
+            | Err(VoteError::DoubleVote { .. })
+            | Err(VoteError::NonMonotonicTime { .. }) => VotePushDecision::AlreadyVoted,
+            Err(VoteError::CooldownActive { remaining }) => match unlock() {
+                Some(usize::MAX) => VotePushDecision::InvalidBranch,
+                unlock => VotePushDecision::WaitSlots(remaining.max(unlock.unwrap_or(0))),
+            },
+            // the branch is locked out until the conflicting votes expire
+            Err(VoteError::InvalidBranch) => match unlock() {
+                Some(usize::MAX) | None => VotePushDecision::InvalidBranch,
+                Some(wait) => VotePushDecision::WaitSlots(wait),
+            },
+            Err(VoteError::FutureVote { slot, current }) => {
+                VotePushDecision::WaitSlots(slot.saturating_sub(current))
+            }
+            Err(VoteError::BelowFinalized) | Err(VoteError::InvalidSignature) => {
+                VotePushDecision::InvalidBranch
+            }
+        }
+    }
+    /// the tower can't vote for `branch` at `current_time` because it is locked to another fork
+    pub fn is_locked_for_branch(
+        &self,
+        branch: &Branch,
+        branch_tree: &BranchTree,
+        current_time: usize,
+    ) -> bool {
+        // a branch that descends from `last_branch` is never locked
+        self.slots_until_unlock(branch, branch_tree, current_time)
+            .is_some()
+    }
+    /// `Vote::is_active_at` with the lockout extended by the grace slots
+    fn is_vote_active(&self, vote: &Vote, time: usize) -> bool {
+        vote.lock_height() + self.grace_slots > time
+    }
+    /// `Vote::slots_until_expiry` with the grace slots, 0 once `is_vote_active` is false
+    fn slots_until_inactive(&self, vote: &Vote, time: usize) -> usize {
+        (vote.lock_height() + self.grace_slots).saturating_sub(time)
+    }
+    /// number of votes that are expired at `time`, including the votes leading up to them
+    fn expired_count(&self, time: usize) -> usize {
+        self.votes
+            .iter()
+            .rposition(|v| v.lock_height() + self.grace_slots < time)
+            .map(|i| i + 1)
+            .unwrap_or(0)
+    }
+    /// if a vote is expired, pop it and all the votes leading up to it
+    fn rollback(&mut self, time: usize) {
+        let expired = self.expired_count(time);
+        if expired == 0 {
+            return;
+        }
+        #[cfg(feature = "tracing")]
+        tracing::warn!(removed = expired, time, "rollback");
+        if self.audit_log.is_some() {
+            let removed = self.pop_expired_votes(time);
+            self.log(|| TowerEvent::VotesRolledBack(removed));
+        } else {
+            // nobody needs the removed votes
+            self.votes.drain(..expired);
+            self.count_rolled_back(expired);
+        }
+    }
+    /// remove the votes that are rolled back at `current_time`, the expired votes and
+    /// all the votes leading up to them
+    /// returns the removed votes oldest first, in the order they were cast
+    pub fn pop_expired_votes(&mut self, current_time: usize) -> Vec<Vote> {
+        let expired = self.expired_count(current_time);
+        if expired == 0 {
+            return vec![];
+        }
+        let mut popped: Vec<_> = self.votes.drain(..expired).collect();
+        self.count_rolled_back(expired);
+        popped.reverse();
+        popped
+    }
+    fn count_rolled_back(&mut self, removed: usize) {
+        self.total_votes_rolled_back += removed;
+        self.prune_removed_votes();
+    }
+    /// forget the gossip votes and double vote proofs whose votes are no longer in the tower,
+    /// a rolled back vote's time is still in `seen_times` and a rooted vote is older than
+    /// the tower
+    fn prune_removed_votes(&mut self) {
+        let votes = &self.votes;
+        let in_tower =
+            |id: usize, time: usize| votes.iter().any(|v| v.branch.id == id && v.time == time);
+        self.gossip_seen.retain(|(id, time)| in_tower(*id, *time));
+        self.double_votes
+            .retain(|time, (existing, _)| in_tower(existing.branch.id, *time));
+    }
+    /// only add votes that are descendent from the last vote in the stack
+    /// and from the finalized branch
+    fn is_valid(&mut self, vote: &Vote, branch_tree: &BranchTree) -> bool {
+        branch_tree.is_finalized_trunk_of(&vote.branch)
+            && self.last_branch().is_trunk_of(&vote.branch, branch_tree)
+    }
+    fn enter_vote(&mut self, vote: Vote) {
+        let vote_time = vote.time;
+        assert!(!self.is_full());
+        assert_eq!(vote.lockout, 2);
+        self.seen_times.insert(vote_time);
+        self.total_votes_cast += 1;
+        self.vote_sequence += 1;
+        self.sequence_at_time.insert(vote_time, self.vote_sequence);
+        // push the new vote to the font
+        self.votes.push_front(vote);
+        // double the lockouts if the threshold to doulbe is met
+        for i in 1..self.votes.len() {
+            assert!(self.votes[i].time <= vote_time);
+            if self.votes[i].lockout == self.votes[i - 1].lockout {
+                self.votes[i].lockout = self.schedule.next_lockout(self.votes[i].lockout, i);
+            }
+        }
+        let accepted = &self.votes[0];
+        if let Some(log) = self.audit_log.as_mut() {
+            log.events.push(TowerEvent::VoteAccepted(accepted.clone()));
+        }
+    }
+    fn pop_full(&mut self) {
+        assert!(self.is_full());
+        let rooted = self.votes.pop_back().unwrap();
+        self.roots.push((rooted.time, rooted.branch.clone()));
+        self.sequence_at_time.retain(|time, _| *time > rooted.time);
+        self.seen_times.retain(|time| *time > rooted.time);
+        self.branch_trunk = rooted.branch;
+        self.phase = TowerPhase::SteadyState;
+        #[cfg(feature = "tracing")]
+        tracing::info!(branch = self.branch_trunk.id, "rooted");
+        let rooted = self.branch_trunk.clone();
+        self.log(|| TowerEvent::BranchRooted(rooted));
+        self.prune_removed_votes();
+    }
+    fn is_full(&self) -> bool {
+        assert!(self.votes.len() <= self.max_size);
+        self.votes.len() == self.max_size
+    }
+    fn last_vote(&self) -> Option<&Vote> {
+        self.votes.front()
+    }
+    fn get_vote(&self, ix: usize) -> Option<&Vote> {
+        self.votes.get(ix)
+    }
+    /// `SteadyState` once the tower has been full, a rollback doesn't go back to `Warmup`
+    pub fn phase(&self) -> TowerPhase {
+        self.phase
+    }
+    /// lockout of the vote at depth `pos`, 0 is the newest vote
+    pub fn lockout_at_position(&self, pos: usize) -> Option<usize> {
+        self.get_vote(pos).map(|v| v.lockout)
+    }
+    /// `(time, grade)` of each vote, newest first
+    /// the grade is the number of votes stacked on top of the vote, capped at 8
+    pub fn commitment_grades(&self) -> Vec<(usize, u8)> {
+        self.votes
+            .iter()
+            .enumerate()
+            .map(|(pos, v)| (v.time, Self::commitment_grade(pos)))
+            .collect()
+    }
+    /// commitment grade of the vote cast at `time`
+    pub fn vote_grade(&self, time: usize) -> Option<u8> {
+        self.votes
+            .iter()
+            .position(|v| v.time == time)
+            .map(Self::commitment_grade)
+    }
+    fn commitment_grade(pos: usize) -> u8 {
+        pos.min(MAX_COMMITMENT_GRADE as usize) as u8
+    }
+    /// the vote at `pos` has double the lockout of the newer vote in front of it
+    pub fn is_lockout_doubled_at(&self, pos: usize) -> bool {
+        pos.checked_sub(1)
+            .and_then(|newer| self.lockout_at_position(newer))
+            .and_then(|newer| self.lockout_at_position(pos).map(|l| l == newer * 2))
+            .unwrap_or(false)
+    }
+    pub fn first_vote(&self) -> Option<&Vote> {
+        self.votes.back()
+    }
+    /// the vote with the highest lockout, the most committed vote in the tower
+    /// it is the vote `pop_full` roots on the next push into a full tower
+    pub fn first_unrooted_vote(&self) -> Option<&Vote> {
+        self.first_vote()
+    }
+    /// number of votes that have not been rooted yet
+    pub fn unrooted_vote_count(&self) -> usize {
+        self.votes.len()
+    }
+    /// the deepest vote whose lockout, extended by the grace slots, has not expired
+    /// at `current_time`
+    pub fn oldest_active_vote(&self, current_time: usize) -> Option<&Vote> {
+        self.votes
+            .iter()
+            .rev()
+            .find(|v| self.is_vote_active(v, current_time))
+    }
+    /// `(time, slots left in the lockout)` of each vote, newest first, 0 for expired votes
+    /// the grace slots count as part of the lockout
+    pub fn lockout_expiry_countdown(&self, current_time: usize) -> Vec<(usize, usize)> {
+        self.votes
+            .iter()
+            .map(|v| (v.time, self.slots_until_inactive(v, current_time)))
+            .collect()
+    }
+    /// votes that are active at `current_time` but not after `within_slots` more slots
+    pub fn votes_expiring_within(
+        &self,
+        current_time: usize,
+        within_slots: usize,
+    ) -> impl Iterator<Item = &Vote> {
+        let expired_at = current_time.saturating_add(within_slots).saturating_add(1);
+        self.votes.iter().filter(move |v| {
+            self.is_vote_active(v, current_time) && !self.is_vote_active(v, expired_at)
+        })
+    }
+    /// lockout of the oldest vote, the strongest commitment in the tower
+    pub fn max_lockout(&self) -> Option<usize> {
+        self.first_vote().map(|v| v.lockout)
+    }
+    /// lockout of the newest vote
+    pub fn min_lockout(&self) -> Option<usize> {
+        self.last_vote().map(|v| v.lockout)
+    }
+    pub fn last_branch(&self) -> Branch {
+        self.last_vote()
+            .map(|v| v.branch.clone())
+            .unwrap_or(self.branch_trunk.clone())
+    }
+    /// how long ago the last vote in the stack was cast
+    pub fn time_since_last_vote(&self, current_time: usize) -> Option<usize> {
+        current_time.checked_sub(self.last_vote()?.time)
+    }
+    /// a tower that hasn't voted in `staleness_threshold` time may need to be reset
+    pub fn is_stale(&self, current_time: usize, staleness_threshold: usize) -> bool {
+        self.time_since_last_vote(current_time)
+            .map(|t| t >= staleness_threshold)
+            .unwrap_or(false)
+    }
+    /// the tower has voted since it was created or reset, every vote rolls back at
+    /// `current_time`, and it never rooted a vote, it needs a reset before it can vote safely
+    /// unlike `is_stale` this doesn't depend on how long ago the last vote was
+    pub fn is_expired_tower(&self, current_time: usize) -> bool {
+        !self.seen_times.is_empty()
+            && self.expired_count(current_time) == self.votes.len()
+            && self.branch_trunk.id == 0
+    }
+    /// time since the last vote of every validator in `network` that `is_stale`
+    /// validators that are voting, or never voted, are left out
+    pub fn vote_staleness_map(
+        network: &[(u64, LockTower)],
+        current_time: usize,
+        staleness_threshold: usize,
+    ) -> HashMap<u64, usize> {
+        network
+            .iter()
+            .filter(|(_, t)| t.is_stale(current_time, staleness_threshold))
+            .filter_map(|(id, t)| Some((*id, t.time_since_last_vote(current_time)?)))
+            .collect()
+    }
+    /// 0 to 100 score of how much of the network has converged on the deepest vote's branch
+    pub fn compute_confidence(&self, converge_map: &ConvergeMap, network_size: usize) -> u8 {
+        if network_size == 0 {
+            return 0;
+        }
+        self.first_vote()
+            .map(|v| {
+                let count = converge_map.get(v.branch.id).min(network_size);
+                (count * 100 / network_size) as u8
+            }).unwrap_or(0)
+    }
+    /// most faulty nodes that still leave `convergence_threshold_pct` percent of the network honest
+    pub fn max_tolerable_faults(network_size: usize, convergence_threshold_pct: usize) -> usize {
+        let threshold = convergence_threshold_pct.min(100);
+        let honest = (network_size * threshold).div_ceil(100);
+        network_size - honest
+    }
+    /// fraction of the network that would need to equivocate for a fork conflicting with the
+    /// deepest vote's branch to also reach a majority, 0.0 if it's not converged
+    pub fn current_safety_margin(&self, converge_map: &ConvergeMap, network_size: usize) -> f64 {
+        if network_size == 0 {
+            return 0.0;
+        }
+        self.first_vote()
+            .map(|v| {
+                let count = converge_map.get(v.branch.id).min(network_size);
+                (count as f64 / network_size as f64 - 0.5).max(0.0)
+            }).unwrap_or(0.0)
+    }
+    /// stake of the validators whose last branch doesn't descend from any vote in the tower
+    /// * all_last_branches - `(stake, last voted branch)` of every validator
+    pub fn compute_switching_stake(
+        &self,
+        branch_tree: &BranchTree,
+        all_last_branches: &[(u64, Branch)],
+    ) -> u64 {
+        all_last_branches
+            .iter()
+            .filter(|(_, last)| {
+                !self
+                    .votes
+                    .iter()
+                    .any(|v| last.is_descendant_of(&v.branch, branch_tree))
+            }).map(|(stake, _)| *stake)
+            .sum()
+    }
+    /// check if enough stake is on forks that diverge from the tower to switch to `new_branch`
+    /// switching to a descendant of the last branch is not a fork switch and is always allowed
+    /// * stake_map - map of `branch id` to the stake that voted for that branch
+    pub fn switch_threshold_check(
+        &self,
+        new_branch: &Branch,
+        branch_tree: &BranchTree,
+        stake_map: &HashMap<usize, u64>,
+        total_stake: u64,
+    ) -> SwitchCheckResult {
+        let last = self.last_branch();
+        // stake on branches that are neither ancestors nor descendants of the last branch
+        let stake: u64 = stake_map
+            .iter()
+            .filter(|(id, _)| {
+                branch_tree
+                    .get(**id)
+                    .map(|b| {
+                        !b.is_trunk_of(&last, branch_tree) && !last.is_trunk_of(b, branch_tree)
+                    })
+                    .unwrap_or(false)
+            }).map(|(_, stake)| *stake)
+            .sum();
+        let stake_fraction = if total_stake == 0 {
+            0.0
+        } else {
+            stake as f64 / total_stake as f64
+        };
+        if last.is_trunk_of(new_branch, branch_tree) || stake_fraction >= SWITCH_THRESHOLD {
+            SwitchCheckResult::Allowed { stake_fraction }
+        } else {
+            SwitchCheckResult::Blocked {
+                required: SWITCH_THRESHOLD,
+                actual: stake_fraction,
+            }
+        }
+    }
+    /// all the votes in the tower, newest first
+    pub fn votes_iter(&self) -> impl DoubleEndedIterator<Item = &Vote> + ExactSizeIterator {
+        self.votes.iter()
+    }
+    /// mutable access to the votes, the caller is responsible for keeping the lockouts valid
+    pub fn votes_iter_mut(&mut self) -> impl DoubleEndedIterator<Item = &mut Vote> {
+        self.votes.iter_mut()
+    }
+    /// how many more votes are needed before the first vote's lockout reaches
+    /// `root_threshold_lockout`, assuming no rollback and ignoring `max_size`
+    /// `None` if the schedule stops growing before the lockout is reached
+    pub fn confirmations_needed(&self, root_threshold_lockout: usize) -> Option<usize> {
+        let mut lockouts: VecDeque<usize> = self.votes.iter().map(|v| v.lockout).collect();
+        let mut needed = 0;
+        let below = |l: Option<&usize>| l.map(|l| *l < root_threshold_lockout).unwrap_or(true);
+        while below(lockouts.back()) {
+            // same doubling as `enter_vote`
+            lockouts.push_front(2);
+            for i in 1..lockouts.len() {
+                if lockouts[i] == lockouts[i - 1] {
+                    let next = self.schedule.next_lockout(lockouts[i], i);
+                    if next <= lockouts[i] {
+                        return None;
+                    }
+                    lockouts[i] = next;
+                }
+            }
+            needed += 1;
+        }
+        Some(needed)
+    }
+    /// fix the lockouts of a tower whose lockouts were corrupted while its times and
+    /// branches are intact
+    /// a lockout that is not in the schedule, or not larger than the newer vote's lockout,
+    /// is replaced with the next lockout in the schedule after the newer vote's lockout
+    /// valid lockouts are kept, like the higher lockouts of votes that survived a rollback
+    /// returns the number of votes whose lockout was replaced
+    pub fn repair_lockouts(&mut self) -> usize {
+        let mut repaired = 0;
+        let mut newer: usize = 0;
+        for (depth, vote) in self.votes.iter_mut().enumerate() {
+            let min = newer.saturating_add(1);
+            let valid = vote.lockout >= min
+                && schedule_lockout_at_least(&*self.schedule, depth, vote.lockout) == vote.lockout;
+            let lockout = if valid {
+                vote.lockout
+            } else {
+                schedule_lockout_at_least(&*self.schedule, depth, min).max(min)
+            };
+            if lockout != vote.lockout {
+                vote.lockout = lockout;
+                repaired += 1;
+            }
+            newer = vote.lockout;
+        }
+        repaired
+    }
+    /// predicted time the first vote is rooted, assuming a vote every slot and no rollback
+    /// `None` for an empty tower, or a full tower whose root is due now
+    pub fn expected_root_slot(&self) -> Option<usize> {
+        let last = self.last_vote()?;
+        let needed = self.max_size.checked_sub(self.votes.len())?;
+        if needed == 0 {
+            return None;
+        }
+        Some(last.time + needed)
+    }
+    /// time of the first vote once its lockout has reached `safety_threshold_lockout`,
+    /// towers like this one will not roll back past it
+    pub fn safe_slot(&self, safety_threshold_lockout: usize) -> Option<usize> {
+        self.first_vote()
+            .filter(|v| v.lockout >= safety_threshold_lockout)
+            .map(|v| v.time)
+    }
+    /// how many more votes are needed before `safe_slot(threshold)` is reported
+    /// returns `None` if the first vote would be popped from the full tower first, or the
+    /// schedule never reaches `threshold`
+    pub fn next_safe_slot_in(&self, threshold: usize) -> Option<usize> {
+        let needed = self.confirmations_needed(threshold)?;
+        if self.votes.len() + needed < self.max_size {
+            Some(needed)
+        } else {
+            None
+        }
+    }
+    /// fraction of the slots in `[chain_start, chain_end]` that are inside the lockout
+    /// window `[time, lock_height + grace_slots)` of at least one vote in the tower
+    pub fn coverage_fraction(&self, chain_start: usize, chain_end: usize) -> f64 {
+        if chain_end < chain_start {
+            return 0.0;
+        }
+        let covered = (chain_start..=chain_end)
+            .filter(|t| {
+                self.votes
+                    .iter()
+                    .any(|v| v.time <= *t && self.is_vote_active(v, *t))
+            }).count();
+        covered as f64 / (chain_end - chain_start + 1) as f64
+    }
+    /// all the branches popped from the full tower, oldest first, the branches of
+    /// `commit_history`
+    pub fn finalized_branches(&self) -> impl DoubleEndedIterator<Item = &Branch> {
+        self.roots.iter().map(|(_, branch)| branch)
+    }
+    /// `branch` is a finalized branch or an ancestor of one
+    pub fn is_finalized(&self, branch: &Branch, branch_tree: &BranchTree) -> bool {
+        self.finalized_branches()
+            .any(|f| branch.is_trunk_of(f, branch_tree))
+    }
+    /// the vote cast at `time`
+    pub fn vote_at_time(&self, time: usize) -> Option<&Vote> {
+        // the tower is small, a `time` index could be added if this becomes a hot path
+        self.votes.iter().find(|v| v.time == time)
+    }
+    /// commitment level of `slot` on `branch`, the branch produced at that slot
+    pub fn slot_commitment_level(
+        &self,
+        slot: usize,
+        branch: &Branch,
+        branch_tree: &BranchTree,
+    ) -> CommitmentLevel {
+        if branch.is_trunk_of(&self.branch_trunk, branch_tree) {
+            return CommitmentLevel::Finalized;
+        }
+        let confirmed = self
+            .vote_at_time(slot)
+            .filter(|v| v.branch.id == branch.id)
+            .map(|v| v.lockout >= CONFIRMED_LOCKOUT)
+            .unwrap_or(false);
+        if confirmed {
+            CommitmentLevel::Confirmed
+        } else {
+            CommitmentLevel::Processed
+        }
+    }
+    /// up to `n` of the most recent votes, newest first
+    pub fn last_n_votes(&self, n: usize) -> impl Iterator<Item = &Vote> {
+        self.votes.iter().take(n)
+    }
+    pub fn vote_count(&self) -> usize {
+        self.votes.len()
+    }
+    /// votes with `from <= vote.time <= to`, newest first
+    pub fn votes_in_range(&self, from: usize, to: usize) -> impl Iterator<Item = &Vote> {
+        // votes are stored newest first
+        self.votes
+            .iter()
+            .skip_while(move |v| v.time > to)
+            .take_while(move |v| v.time >= from)
+    }
+    /// votes with `vote.time < time`, newest first
+    pub fn get_votes_before(&self, time: usize) -> impl Iterator<Item = &Vote> {
+        self.votes.iter().skip_while(move |v| v.time >= time)
+    }
+    /// votes with `vote.time > time`, newest first
+    pub fn get_votes_after(&self, time: usize) -> impl Iterator<Item = &Vote> {
+        self.votes.iter().take_while(move |v| v.time > time)
+    }
+    /// the votes grouped by the epoch of their time, newest first in each epoch
+    pub fn epoch_votes<F>(&self, slot_to_epoch: F) -> HashMap<u64, Vec<&Vote>>
+    where
+        F: Fn(usize) -> u64,
+    {
+        let mut epochs: HashMap<u64, Vec<&Vote>> = HashMap::new();
+        for vote in &self.votes {
+            epochs
+                .entry(slot_to_epoch(vote.time))
+                .or_default()
+                .push(vote);
+        }
+        epochs
+    }
+    /// the votes whose time is in `epoch`, newest first
+    pub fn votes_in_epoch<F>(&self, epoch: u64, slot_to_epoch: F) -> impl Iterator<Item = &Vote>
+    where
+        F: Fn(usize) -> u64,
+    {
+        self.votes
+            .iter()
+            .filter(move |v| slot_to_epoch(v.time) == epoch)
+    }
+    /// votes for `branch` or one of its ancestors, newest first
+    pub fn votes_for_branch<'a>(
+        &'a self,
+        branch: &Branch,
+        tree: &'a BranchTree,
+    ) -> impl Iterator<Item = &'a Vote> + 'a {
+        let branch = branch.clone();
+        self.votes
+            .iter()
+            .filter(move |v| v.branch.is_trunk_of(&branch, tree))
+    }
+    /// sum of the lockouts of all the votes
+    pub fn tower_weight(&self) -> usize {
+        self.votes.iter().map(|v| v.lockout).sum()
+    }
+    /// average age of the votes at `current_time` weighted by their lockouts
+    /// 0 for an empty tower
+    pub fn time_weighted_commitment(&self, current_time: usize) -> f64 {
+        let weight = self.tower_weight();
+        if weight == 0 {
+            return 0.0;
+        }
+        let aged: usize = self
+            .votes
+            .iter()
+            .map(|v| v.lockout * current_time.saturating_sub(v.time))
+            .sum();
+        aged as f64 / weight as f64
+    }
+    /// `tower_weight` scaled by the validator's fraction of `total_stake`
+    pub fn weighted_tower_strength(&self, validator_stake: u64, total_stake: u64) -> f64 {
+        if total_stake == 0 {
+            return 0.0;
+        }
+        (self.tower_weight() as f64 * validator_stake as f64) / total_stake as f64
+    }
+    /// sum of the `weighted_tower_strength` of every `(tower, stake)`
+    pub fn network_tower_strength(towers: &[(LockTower, u64)], total_stake: u64) -> f64 {
+        towers
+            .iter()
+            .map(|(tower, stake)| tower.weighted_tower_strength(*stake, total_stake))
+            .sum()
+    }
+    /// the sum of the lockouts of a tower with `max_size` votes that each grew by `multiplier`
+    /// from `initial_lockout`, the fewest slots before the first vote of a fresh tower is rooted
+    pub fn min_slots_to_finalize(
+        max_size: usize,
+        initial_lockout: usize,
+        multiplier: usize,
+    ) -> usize {
+        (0..max_size)
+            .scan(initial_lockout, |lockout, _| {
+                let current = *lockout;
+                *lockout = lockout.saturating_mul(multiplier);
+                Some(current)
+            }).fold(0, |sum: usize, lockout| sum.saturating_add(lockout))
+    }
+    /// lockout of the oldest vote in a tower that voted every slot for `total_slots_elapsed`
+    fn max_possible_lockout(&self, total_slots_elapsed: usize) -> usize {
+        let depth = self.max_size.saturating_sub(1).min(total_slots_elapsed);
+        (1..depth).fold(2, |lockout, i| self.schedule.next_lockout(lockout, i))
+    }
+    /// a 0.0 to 1.0 score of the network
+    /// * 0.5 - fraction of nodes whose last vote is on the fork of the most agreed upon tip
+    /// * 0.3 - average `max_lockout` relative to the largest possible after `total_slots_elapsed`
+    /// * 0.2 - fraction of nodes with non-empty towers
+    pub fn network_health_score(
+        network: &[LockTower],
+        branch_tree: &BranchTree,
+        total_slots_elapsed: usize,
+    ) -> f64 {
+        if network.is_empty() {
+            return 0.0;
+        }
+        let size = network.len() as f64;
+        let tips: Vec<Branch> = network.iter().map(|n| n.last_branch()).collect();
+        let on_fork = |tip: &Branch| {
+            tips.iter()
+                .filter(|t| t.is_trunk_of(tip, branch_tree))
+                .count()
+        };
+        let converged = tips.iter().map(on_fork).max().unwrap_or(0);
+        let lockout: f64 = network
+            .iter()
+            .map(|n| {
+                let max = n.max_possible_lockout(total_slots_elapsed) as f64;
+                let lockout = n.max_lockout().unwrap_or(0) as f64;
+                (lockout / max).min(1.0)
+            }).sum();
+        let voted = network.iter().filter(|n| !n.votes.is_empty()).count();
+        0.5 * converged as f64 / size + 0.3 * lockout / size + 0.2 * voted as f64 / size
+    }
+    /// the newest vote time that more than `quorum_fraction` of the network is locked out on
+    /// a node covers a vote if it has a vote at that time or later, on the vote's branch or
+    /// a descendant, that is still locked out at `current_time`
+    pub fn quorum_root(
+        network: &[LockTower],
+        branch_tree: &BranchTree,
+        current_time: usize,
+        quorum_fraction: f64,
+    ) -> Option<usize> {
+        let mut candidates: Vec<&Vote> = network.iter().flat_map(|n| n.votes.iter()).collect();
+        candidates.sort_by_key(|v| (Reverse(v.time), v.branch.id));
+        candidates.dedup_by_key(|v| (v.time, v.branch.id));
+        let quorum = network.len() as f64 * quorum_fraction;
+        candidates
+            .into_iter()
+            .find(|candidate| {
+                let covered = network
+                    .iter()
+                    .filter(|n| {
+                        n.votes.iter().any(|v| {
+                            v.time >= candidate.time
+                                && n.is_vote_active(v, current_time)
+                                && candidate.is_trunk_of(v, branch_tree)
+                        })
+                    }).count();
+                covered as f64 > quorum
+            }).map(|v| v.time)
+    }
+    /// check if more than 2/3 of `total_stake` has voted for `branch` or one of its descendants
+    /// a vote for a descendant also votes for `branch`, a vote for an ancestor doesn't, so
+    /// the stake of the ancestors is not counted and `branch_tree` is needed to find the
+    /// descendants
+    /// * branch_votes - map of `branch id` to the stake that voted directly for that branch,
+    ///   already weighted by stake so no separate stake map is taken, see
+    ///   `OptimisticConfirmationTracker::branch_votes`
+    pub fn optimistic_confirmation(
+        branch: &Branch,
+        branch_tree: &BranchTree,
+        branch_votes: &HashMap<usize, u64>,
+        total_stake: u64,
+    ) -> bool {
+        let stake: u64 = branch_votes
+            .iter()
+            .filter(|(id, _)| match branch_tree.get(**id) {
+                Some(b) => branch.is_trunk_of(b, branch_tree),
+                None => **id == branch.id,
+            }).map(|(_, stake)| *stake)
+            .sum();
+        3 * stake > 2 * total_stake
+    }
+    /// update a map of `branch id` to `node count` for one node whose last branch changed
+    /// from `removed_branch` to `added_branch`, without recomputing the whole network
+    pub fn apply_convergence_update(
+        old_map: &mut HashMap<usize, usize>,
+        removed_branch: &Branch,
+        added_branch: &Branch,
+        branch_tree: &BranchTree,
+    ) {
+        // same walk as the full computation, the branch and its ancestors in the tree
+        let ancestry = |branch: &Branch| {
+            let mut ids = vec![branch.id];
+            let mut start = branch_tree.get(branch.base);
+            while let Some(b) = start {
+                ids.push(b.id);
+                start = branch_tree.get(b.base);
+            }
+            ids
+        };
+        for id in ancestry(removed_branch) {
+            let count = old_map.get(&id).cloned().unwrap_or(0);
+            assert!(count > 0);
+            if count == 1 {
+                old_map.remove(&id);
+            } else {
+                old_map.insert(id, count - 1);
+            }
+        }
+        for id in ancestry(added_branch) {
+            *old_map.entry(id).or_insert(0) += 1;
+        }
+    }
+}
+
+/// maintains the stake that voted for each branch as new votes arrive
+/// only the latest vote of each voter is counted
+#[derive(Debug, Default)]
+pub struct OptimisticConfirmationTracker {
+    /// map of `voter id` to `stake`
+    stake_map: HashMap<usize, u64>,
+    /// map of `voter id` to the `branch id` of its latest vote
+    last_votes: HashMap<usize, usize>,
+    /// map of `branch id` to the stake that voted for it
+    branch_votes: HashMap<usize, u64>,
+}
+
+impl OptimisticConfirmationTracker {
+    pub fn new(stake_map: HashMap<usize, u64>) -> Self {
+        Self {
+            stake_map,
+            last_votes: HashMap::new(),
+            branch_votes: HashMap::new(),
+        }
+    }
+    /// record the latest vote from `voter`, replacing its previous vote
+    /// voters without stake are ignored, they can't move the branch stake
+    pub fn record_vote(&mut self, voter: usize, branch: &Branch) {
+        let stake = *self.stake_map.get(&voter).unwrap_or(&0);
+        if stake == 0 {
+            return;
+        }
+        if let Some(old) = self.last_votes.insert(voter, branch.id) {
+            let remove = match self.branch_votes.get_mut(&old) {
+                Some(s) => {
+                    *s -= stake;
+                    *s == 0
+                }
+                None => false,
+            };
+            if remove {
+                self.branch_votes.remove(&old);
+            }
+        }
+        *self.branch_votes.entry(branch.id).or_insert(0) += stake;
+    }
+    pub fn branch_votes(&self) -> &HashMap<usize, u64> {
+        &self.branch_votes
+    }
+    pub fn total_stake(&self) -> u64 {
+        self.stake_map.values().sum()
+    }
+    pub fn is_confirmed(&self, branch: &Branch, branch_tree: &BranchTree) -> bool {
+        LockTower::optimistic_confirmation(
+            branch,
+            branch_tree,
+            &self.branch_votes,
+            self.total_stake(),
+        )
+    }
+}
+
+/// `LockTower` that can be shared between threads
+#[derive(Debug)]
+pub struct ConcurrentLockTower {
+    tower: Mutex<LockTower>,
+}
+
+impl ConcurrentLockTower {
+    pub fn new(tower: LockTower) -> Self {
+        Self {
+            tower: Mutex::new(tower),
+        }
+    }
+    /// lock the tower for any other access
+    pub fn lock(&self) -> MutexGuard<'_, LockTower> {
+        self.tower.lock().unwrap()
+    }
+    pub fn into_inner(self) -> LockTower {
+        self.tower.into_inner().unwrap()
+    }
+    /// `try_push_vote` only if the tower's last branch is still `expected_last_branch_id`
+    /// returns `Ok(false)` without pushing if another thread changed the last branch first
+    pub fn push_vote_cas(
+        &self,
+        vote: Vote,
+        expected_last_branch_id: usize,
+        branch_tree: &BranchTree,
+        converge_map: &ConvergeMap,
+        depth: usize,
+    ) -> Result<bool, VoteError> {
+        let mut tower = self.lock();
+        if tower.last_branch().id != expected_last_branch_id {
+            return Ok(false);
+        }
+        tower
+            .try_push_vote(vote, branch_tree, converge_map, depth)
+            .map(|_| true)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand::{thread_rng, Rng};
+    use simulator;
+
+    #[test]
+    fn test_branch_eq_by_id() {
+        assert_eq!(Branch { id: 1, base: 0 }, Branch { id: 1, base: 2 });
+        assert_ne!(Branch { id: 1, base: 0 }, Branch { id: 2, base: 0 });
+        let branches = vec![
+            Branch { id: 1, base: 0 },
+            Branch { id: 1, base: 2 },
+            Branch { id: 2, base: 1 },
+        ];
+        let set: HashSet<_> = branches.into_iter().collect();
+        assert_eq!(set.len(), 2);
+    }
+
+    #[test]
+    fn test_is_trunk_of_1() {
+        let tree = BranchTree::default();
+        let b1 = Branch { id: 1, base: 0 };
+        let b2 = Branch { id: 2, base: 0 };
+        assert!(!b1.is_trunk_of(&b2, &tree));
+    }
+    #[test]
+    fn test_is_trunk_of_2() {
+        let tree = BranchTree::default();
+        let b1 = Branch { id: 1, base: 0 };
+        let b2 = Branch { id: 0, base: 0 };
+        assert!(!b1.is_trunk_of(&b2, &tree));
+    }
+    #[test]
+    fn test_is_trunk_of_3() {
+        let tree = BranchTree::default();
+        let b1 = Branch { id: 1, base: 0 };
+        let b2 = Branch { id: 1, base: 0 };
+        assert!(b1.is_trunk_of(&b2, &tree));
+    }
+    #[test]
+    fn test_is_trunk_of_4() {
+        let mut tree = BranchTree::default();
+        let b1 = Branch { id: 1, base: 0 };
+        let b2 = Branch { id: 2, base: 1 };
+        tree.insert(b1.clone()).unwrap();
+        assert!(b1.is_trunk_of(&b2, &tree));
+        assert!(!b2.is_trunk_of(&b1, &tree));
+    }
+    #[test]
+    fn test_is_descendant_of() {
+        let mut tree = BranchTree::default();
+        let b0 = Branch { id: 0, base: 0 };
+        let b1 = Branch { id: 1, base: 0 };
+        let b2 = Branch { id: 2, base: 0 };
+        let b3 = Branch { id: 2, base: 1 };
+        tree.insert(b1.clone()).unwrap();
+        let branches = [&b0, &b1, &b2, &b3];
+        for a in &branches {
+            for b in &branches {
+                assert_eq!(a.is_trunk_of(b, &tree), b.is_descendant_of(a, &tree));
+            }
+        }
+        assert!(b3.is_descendant_of(&b1, &tree));
+        assert!(!b1.is_descendant_of(&b3, &tree));
+        assert!(b1.is_descendant_of(&b1, &tree));
+        assert!(!b1.is_descendant_of(&b2, &tree));
+    }
+    #[test]
+    fn test_branch_tree_insert() {
+        let mut tree = BranchTree::new();
+        assert_eq!(
+            tree.insert(Branch { id: 0, base: 0 }),
+            Err(BranchTreeError::RootBranch)
+        );
+        assert_eq!(tree.insert(Branch { id: 1, base: 0 }), Ok(()));
+        assert_eq!(tree.insert(Branch { id: 1, base: 0 }), Ok(()));
+        assert_eq!(
+            tree.insert(Branch { id: 1, base: 2 }),
+            Err(BranchTreeError::Conflict { id: 1 })
+        );
+        assert_eq!(
+            tree.insert(Branch { id: 3, base: 3 }),
+            Err(BranchTreeError::Cycle { id: 3 })
+        );
+        // 5 is an orphan until 4 arrives, 4 can't descend from 5
+        assert_eq!(tree.insert(Branch { id: 5, base: 4 }), Ok(()));
+        assert_eq!(
+            tree.insert(Branch { id: 4, base: 5 }),
+            Err(BranchTreeError::Cycle { id: 4 })
+        );
+        assert_eq!(tree.len(), 2);
+    }
+    #[test]
+    fn test_branch_tree_insert_batch() {
+        let mut tree = BranchTree::new();
+        tree.insert(Branch { id: 1, base: 0 }).unwrap();
+        let batch = vec![
+            Branch { id: 1, base: 0 },
+            Branch { id: 2, base: 1 },
+            Branch { id: 3, base: 2 },
+        ];
+        assert_eq!(tree.insert_batch(batch), Ok(2));
+        assert_eq!(tree.len(), 3);
+
+        let batch = vec![
+            Branch { id: 4, base: 3 },
+            Branch { id: 6, base: 5 },
+            Branch { id: 5, base: 6 },
+            Branch { id: 7, base: 4 },
+        ];
+        assert_eq!(
+            tree.insert_batch(batch),
+            Err((BranchTreeError::Cycle { id: 5 }, 2))
+        );
+        assert_eq!(tree.len(), 3);
+        assert!(tree.contains(1));
+        assert!(tree.contains(3));
+        assert!(!tree.contains(4));
+        assert!(!tree.contains(6));
+    }
+    #[test]
+    fn test_branch_tree_lca() {
+        let mut tree = BranchTree::default();
+        let b1 = Branch { id: 1, base: 0 };
+        let b2 = Branch { id: 2, base: 1 };
+        let b3 = Branch { id: 3, base: 1 };
+        let b4 = Branch { id: 4, base: 3 };
+        let b5 = Branch { id: 5, base: 0 };
+        let orphan = Branch { id: 7, base: 6 };
+        for b in &[&b1, &b2, &b3, &b4, &b5, &orphan] {
+            tree.insert((*b).clone()).unwrap();
+        }
+        let lca = |a: &Branch, b: &Branch| tree.lca(a, b).map(|b| b.id);
+        assert_eq!(lca(&b2, &b2), Some(2));
+        assert_eq!(lca(&b1, &b2), Some(1));
+        assert_eq!(lca(&b4, &b1), Some(1));
+        assert_eq!(lca(&b2, &b3), Some(1));
+        assert_eq!(lca(&b2, &b4), Some(1));
+        assert_eq!(lca(&b4, &b5), Some(0));
+        assert_eq!(lca(&Branch::default(), &b4), Some(0));
+        assert_eq!(lca(&orphan, &b4), None);
+        assert_eq!(lca(&orphan, &Branch::default()), None);
+        assert_eq!(lca(&orphan, &orphan), Some(7));
+    }
+
+    #[test]
+    fn test_fork_count_at_depth() {
+        let mut tree = BranchTree::default();
+        assert_eq!(tree.max_fork_depth(), 0);
+        for id in 1..5 {
+            tree.insert(Branch { id, base: id - 1 }).unwrap();
+        }
+        assert_eq!(tree.fork_count_at_depth(0), 0);
+        assert!((1..5).all(|depth| tree.fork_count_at_depth(depth) == 1));
+        assert_eq!(tree.fork_count_at_depth(5), 0);
+        assert_eq!(tree.max_fork_depth(), 4);
+
+        // fork at branch 2
+        tree.insert(Branch { id: 5, base: 2 }).unwrap();
+        tree.insert(Branch { id: 6, base: 5 }).unwrap();
+        tree.insert(Branch { id: 7, base: 6 }).unwrap();
+        assert_eq!(tree.fork_count_at_depth(2), 1);
+        assert_eq!(tree.fork_count_at_depth(3), 2);
+        assert_eq!(tree.fork_count_at_depth(4), 2);
+        assert_eq!(tree.fork_count_at_depth(5), 1);
+        assert_eq!(tree.max_fork_depth(), 5);
+    }
+
+    #[test]
+    fn test_find_heaviest_fork() {
+        let mut tree = BranchTree::default();
+        let mut votes = HashMap::new();
+        assert!(tree.find_heaviest_fork(&votes, 0).is_none());
+        tree.insert(Branch { id: 1, base: 0 }).unwrap();
+        assert_eq!(tree.find_heaviest_fork(&votes, 0).map(|b| b.id), Some(1));
+        assert_eq!(tree.find_heaviest_fork(&votes, 1).map(|b| b.id), Some(1));
+
+        tree.insert(Branch { id: 2, base: 1 }).unwrap();
+        tree.insert(Branch { id: 3, base: 1 }).unwrap();
+        votes.insert(2, 10);
+        votes.insert(3, 5);
+        assert_eq!(tree.find_heaviest_fork(&votes, 0).map(|b| b.id), Some(2));
+        votes.insert(3, 10);
+        assert_eq!(tree.find_heaviest_fork(&votes, 0).map(|b| b.id), Some(3));
+
+        // the subtree of 2 is heavier even though 3 has the most direct votes
+        tree.insert(Branch { id: 4, base: 2 }).unwrap();
+        tree.insert(Branch { id: 5, base: 2 }).unwrap();
+        votes.insert(4, 4);
+        votes.insert(5, 3);
+        assert_eq!(tree.find_heaviest_fork(&votes, 0).map(|b| b.id), Some(4));
+        assert_eq!(tree.find_heaviest_fork(&votes, 3).map(|b| b.id), Some(3));
+    }
+
+    #[test]
+    fn test_to_adjacency_list() {
+        let mut tree = BranchTree::default();
+        assert!(tree.to_adjacency_list().is_empty());
+        assert!(tree.branch_ids().is_empty());
+        for id in (1..5).rev() {
+            tree.insert(Branch { id, base: id - 1 }).unwrap();
+        }
+        assert_eq!(
+            tree.to_adjacency_list(),
+            vec![(0, 1), (1, 2), (2, 3), (3, 4)]
+        );
+        tree.insert(Branch { id: 7, base: 1 }).unwrap();
+        tree.insert(Branch { id: 5, base: 7 }).unwrap();
+        assert_eq!(tree.branch_ids(), vec![1, 2, 3, 4, 5, 7]);
+
+        let edges = tree.to_adjacency_list();
+        let mut rebuilt = BranchTree::default();
+        for (base, id) in edges {
+            rebuilt.insert(Branch { id, base }).unwrap();
+        }
+        assert_eq!(rebuilt.branch_ids(), tree.branch_ids());
+        let same_base = tree
+            .branch_ids()
+            .into_iter()
+            .all(|id| rebuilt.get(id).unwrap().base == tree.get(id).unwrap().base);
+        assert!(same_base);
+        assert!(rebuilt.orphans().is_empty());
+    }
+
+    #[test]
+    fn test_depth_histogram() {
+        let mut tree = BranchTree::default();
+        assert!(tree.depth_histogram().is_empty());
+        assert_eq!(tree.mean_depth(), 0.0);
+        assert_eq!(tree.max_depth(), 0);
+
+        tree.insert(Branch { id: 1, base: 0 }).unwrap();
+        assert_eq!(tree.depth_histogram(), vec![0, 1]);
+        assert_eq!(tree.mean_depth(), 1.0);
+
+        // balanced binary tree under branch 1
+        for id in 2..8 {
+            tree.insert(Branch { id, base: id / 2 }).unwrap();
+        }
+        assert_eq!(tree.depth_histogram(), vec![0, 1, 2, 4]);
+        assert_eq!(tree.mean_depth(), 17.0 / 7.0);
+        assert_eq!(tree.max_depth(), 3);
+    }
+
+    #[test]
+    fn test_common_ancestors() {
+        let mut tree = BranchTree::default();
+        let b1 = Branch { id: 1, base: 0 };
+        let b2 = Branch { id: 2, base: 1 };
+        let b3 = Branch { id: 3, base: 1 };
+        let b4 = Branch { id: 4, base: 2 };
+        let orphan = Branch { id: 7, base: 6 };
+        for b in &[&b1, &b2, &b3, &b4, &orphan] {
+            tree.insert((*b).clone()).unwrap();
+        }
+        let ids = |branches: &[&Branch]| -> Vec<usize> {
+            tree.common_ancestors(branches)
+                .iter()
+                .map(|b| b.id)
+                .collect()
+        };
+        assert_eq!(ids(&[&b4]), vec![4, 2, 1, 0]);
+        assert_eq!(ids(&[&b4, &b2]), vec![2, 1, 0]);
+        assert_eq!(ids(&[&b4, &b3, &b2]), vec![1, 0]);
+        assert!(ids(&[&b4, &orphan]).is_empty());
+        assert_eq!(ids(&[]), vec![4, 2, 3, 1, 7, 0]);
+    }
+
+    #[test]
+    fn test_branch_tree_orphans() {
+        let mut tree = BranchTree::new();
+        tree.insert(Branch { id: 1, base: 0 }).unwrap();
+        tree.insert(Branch { id: 2, base: 1 }).unwrap();
+        assert!(tree.orphans().is_empty());
+
+        tree.insert(Branch { id: 4, base: 3 }).unwrap();
+        let orphans: Vec<_> = tree.orphans().iter().map(|b| b.id).collect();
+        assert_eq!(orphans, vec![4]);
+
+        assert_eq!(
+            tree.reattach_orphan(2, 1),
+            Err(BranchTreeError::NotOrphan { id: 2 })
+        );
+        assert_eq!(
+            tree.reattach_orphan(5, 1),
+            Err(BranchTreeError::NotFound { id: 5 })
+        );
+        tree.insert(Branch { id: 5, base: 4 }).unwrap();
+        assert_eq!(
+            tree.reattach_orphan(4, 5),
+            Err(BranchTreeError::Cycle { id: 4 })
+        );
+        assert_eq!(tree.reattach_orphan(4, 2), Ok(()));
+        assert!(tree.orphans().is_empty());
+        assert_eq!(tree.get(5).unwrap().depth(&tree), 4);
+    }
+    #[test]
+    fn test_push_vote() {
+        let tree = BranchTree::default();
+        let bmap = ConvergeMap::default();
+        let b0 = Branch { id: 0, base: 0 };
+        let mut node = LockTower::new(32);
+        let vote = Vote::new(b0.clone(), 0);
+        assert!(node.push_vote(vote, &tree, &bmap, 32));
+        assert_eq!(node.votes.len(), 1);
+
+        let vote = Vote::new(b0.clone(), 1);
+        assert!(node.push_vote(vote, &tree, &bmap, 32));
+        assert_eq!(node.votes.len(), 2);
+
+        let vote = Vote::new(b0.clone(), 2);
+        assert!(node.push_vote(vote, &tree, &bmap, 32));
+        assert_eq!(node.votes.len(), 3);
+
+        let vote = Vote::new(b0.clone(), 3);
+        assert!(node.push_vote(vote, &tree, &bmap, 32));
+        assert_eq!(node.votes.len(), 4);
+
+        assert_eq!(node.votes[0].lockout, 2);
+        assert_eq!(node.votes[1].lockout, 4);
+        assert_eq!(node.votes[2].lockout, 8);
+        assert_eq!(node.votes[3].lockout, 16);
+        assert_eq!(node.lockout_at_position(3), Some(16));
+        assert_eq!(node.lockout_at_position(4), None);
+        assert!(!node.is_lockout_doubled_at(0));
+        assert!((1..4).all(|pos| node.is_lockout_doubled_at(pos)));
+        assert!(!node.is_lockout_doubled_at(4));
+
+        assert_eq!(node.votes[1].lock_height(), 6);
+        assert_eq!(node.votes[2].lock_height(), 9);
+
+        let vote = Vote::new(b0.clone(), 7);
+        assert!(node.push_vote(vote, &tree, &bmap, 32));
+
+        assert_eq!(node.votes[0].lockout, 2);
+
+        let b1 = Branch { id: 1, base: 1 };
+        let vote = Vote::new(b1.clone(), 8);
+        assert!(!node.push_vote(vote, &tree, &bmap, 32));
+
+        let vote = Vote::new(b0.clone(), 8);
+        assert!(node.push_vote(vote, &tree, &bmap, 32));
+
+        assert_eq!(node.votes.len(), 4);
+        assert_eq!(node.votes[0].lockout, 2);
+        assert_eq!(node.votes[1].lockout, 4);
+        assert_eq!(node.votes[2].lockout, 8);
+        assert_eq!(node.votes[3].lockout, 16);
+
+        let vote = Vote::new(b0.clone(), 10);
+        assert!(node.push_vote(vote, &tree, &bmap, 32));
+        assert_eq!(node.votes.len(), 2);
+        assert_eq!(node.votes[0].lockout, 2);
+        assert_eq!(node.votes[1].lockout, 16);
+        assert!(!node.is_lockout_doubled_at(1));
+    }
+
+    #[test]
+    fn test_is_stale() {
+        let tree = BranchTree::default();
+        let bmap = ConvergeMap::default();
+        let b0 = Branch { id: 0, base: 0 };
+        let mut node = LockTower::new(32);
+        assert_eq!(node.time_since_last_vote(10), None);
+        assert!(!node.is_stale(100, 50));
+        assert!(node.push_vote(Vote::new(b0, 10), &tree, &bmap, 32));
+        assert_eq!(node.time_since_last_vote(5), None);
+        assert_eq!(node.time_since_last_vote(60), Some(50));
+        assert!(node.is_stale(60, 50));
+        assert!(!node.is_stale(59, 50));
+    }
+
+    #[test]
+    fn test_optimistic_confirmation() {
+        let mut tree = BranchTree::default();
+        let b1 = Branch { id: 1, base: 0 };
+        let b2 = Branch { id: 2, base: 1 };
+        tree.insert(b1.clone()).unwrap();
+        tree.insert(b2.clone()).unwrap();
+        let stake_map = (0..100).map(|i| (i, 1)).collect();
+        let mut tracker = OptimisticConfirmationTracker::new(stake_map);
+        assert_eq!(tracker.total_stake(), 100);
+        for voter in 0..66 {
+            tracker.record_vote(voter, &b2);
+        }
+        assert!(!tracker.is_confirmed(&b2, &tree));
+        assert!(!tracker.is_confirmed(&b1, &tree));
+        tracker.record_vote(66, &b2);
+        assert!(tracker.is_confirmed(&b2, &tree));
+        // votes for a descendant confirm the ancestor
+        assert!(tracker.is_confirmed(&b1, &tree));
+
+        // a voter switching its vote only counts once
+        tracker.record_vote(66, &b1);
+        assert!(!tracker.is_confirmed(&b2, &tree));
+        assert!(tracker.is_confirmed(&b1, &tree));
+        assert_eq!(tracker.branch_votes().get(&1), Some(&1));
+        assert_eq!(tracker.branch_votes().get(&2), Some(&66));
+
+        // a voter without stake moving off a branch that lost all its stake
+        let stake_map = Some((1, 5)).into_iter().collect();
+        let mut tracker = OptimisticConfirmationTracker::new(stake_map);
+        tracker.record_vote(1, &b1);
+        tracker.record_vote(2, &b1);
+        tracker.record_vote(1, &b2);
+        assert!(tracker.branch_votes().get(&1).is_none());
+        tracker.record_vote(2, &b2);
+        assert_eq!(tracker.branch_votes().get(&2), Some(&5));
+        assert_eq!(tracker.branch_votes().len(), 1);
+    }
+
+    #[test]
+    fn test_is_safe_to_vote() {
+        let tree = BranchTree::default();
+        let bmap = ConvergeMap::default();
+        let b0 = Branch { id: 0, base: 0 };
+        let mut node = LockTower::new(32);
+        for time in 0..4 {
+            assert!(node.push_vote(Vote::new(b0.clone(), time), &tree, &bmap, 32));
+        }
+        let vote = Vote::new(b0.clone(), 4);
+        assert_eq!(node.is_safe_to_vote(&vote, &tree, &bmap, 32), Ok(()));
+
+        let vote = Vote::new(Branch { id: 1, base: 1 }, 4);
+        assert_eq!(
+            node.is_safe_to_vote(&vote, &tree, &bmap, 32),
+            Err(VoteError::InvalidBranch)
+        );
+
+        let vote = Vote::new(b0.clone(), 4);
+        assert_eq!(
+            node.is_safe_to_vote(&vote, &tree, &bmap, 1),
+            Err(VoteError::NotConverged)
+        );
+        let mut converged = ConvergeMap::default();
+        converged.set(0, 51);
+        assert_eq!(node.is_safe_to_vote(&vote, &tree, &converged, 1), Ok(()));
+
+        let vote = Vote::new(b0.clone(), 2);
+        assert_eq!(
+            node.is_safe_to_vote(&vote, &tree, &bmap, 32),
+            Err(VoteError::OldVote {
+                last_time: 3,
+                vote_time: 2
+            })
+        );
+        // the tower is not modified
+        assert_eq!(node.votes.len(), 4);
+    }
+    #[test]
+    fn test_is_safe_to_vote_after_rollback() {
+        let mut tree = BranchTree::default();
+        let bmap = ConvergeMap::default();
+        let b1 = Branch { id: 1, base: 0 };
+        let b2 = Branch { id: 2, base: 1 };
+        let b3 = Branch { id: 3, base: 1 };
+        tree.insert(b1.clone()).unwrap();
+        tree.insert(b2.clone()).unwrap();
+        tree.insert(b3.clone()).unwrap();
+        let mut node = LockTower::new(32);
+        assert!(node.push_vote(Vote::new(b1.clone(), 0), &tree, &bmap, 32));
+        assert!(node.push_vote(Vote::new(b2.clone(), 1), &tree, &bmap, 32));
+
+        // b2 is still locked out
+        let vote = Vote::new(b3.clone(), 3);
+        assert_eq!(
+            node.is_safe_to_vote(&vote, &tree, &bmap, 32),
+            Err(VoteError::InvalidBranch)
+        );
+
+        // b2 expires at time 3
+        let vote = Vote::new(b3.clone(), 4);
+        assert_eq!(node.is_safe_to_vote(&vote, &tree, &bmap, 32), Ok(()));
+        assert!(node.push_vote(vote, &tree, &bmap, 32));
+    }
+
+    #[test]
+    fn test_votes_in_range() {
+        let tree = BranchTree::default();
+        let bmap = ConvergeMap::default();
+        let b0 = Branch { id: 0, base: 0 };
+        let mut node = LockTower::new(32);
+        for time in 0..4 {
+            assert!(node.push_vote(Vote::new(b0.clone(), time), &tree, &bmap, 32));
+        }
+        let times = |node: &LockTower, from, to| -> Vec<usize> {
+            node.votes_in_range(from, to).map(|v| v.time).collect()
+        };
+        assert_eq!(times(&node, 0, 3), vec![3, 2, 1, 0]);
+        assert_eq!(times(&node, 1, 2), vec![2, 1]);
+        assert_eq!(times(&node, 2, 2), vec![2]);
+        assert_eq!(times(&node, 5, 5), Vec::<usize>::new());
+        assert_eq!(times(&node, 3, 1), Vec::<usize>::new());
+
+        // votes 2 and 3 are rolled back at time 7
+        assert!(node.push_vote(Vote::new(b0.clone(), 7), &tree, &bmap, 32));
+        assert_eq!(times(&node, 1, 7), vec![7, 1]);
+        assert_eq!(times(&node, 2, 3), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn test_commitment_grades() {
+        let tree = BranchTree::default();
+        let bmap = ConvergeMap::default();
+        let b0 = Branch { id: 0, base: 0 };
+        let mut node = LockTower::new(32);
+        assert!(node.commitment_grades().is_empty());
+        for time in 0..4 {
+            assert!(node.push_vote(Vote::new(b0.clone(), time), &tree, &bmap, 32));
+        }
+        assert_eq!(
+            node.commitment_grades(),
+            vec![(3, 0), (2, 1), (1, 2), (0, 3)]
+        );
+        assert_eq!(node.vote_grade(3), Some(0));
+        assert_eq!(node.vote_grade(0), Some(3));
+        assert_eq!(node.vote_grade(4), None);
+        for time in 4..12 {
+            assert!(node.push_vote(Vote::new(b0.clone(), time), &tree, &bmap, 32));
+        }
+        assert_eq!(node.vote_grade(0), Some(MAX_COMMITMENT_GRADE));
+    }
+
+    #[test]
+    fn test_lockout_expiry_countdown() {
+        let tree = BranchTree::default();
+        let bmap = ConvergeMap::default();
+        let b0 = Branch { id: 0, base: 0 };
+        let mut node = LockTower::new(32);
+        for time in 0..3 {
+            assert!(node.push_vote(Vote::new(b0.clone(), time), &tree, &bmap, 32));
+        }
+        // lock heights are 8, 5 and 4
+        assert_eq!(
+            node.lockout_expiry_countdown(2),
+            vec![(2, 2), (1, 3), (0, 6)]
+        );
+        assert_eq!(
+            node.lockout_expiry_countdown(3),
+            vec![(2, 1), (1, 2), (0, 5)]
+        );
+        assert_eq!(
+            node.lockout_expiry_countdown(6),
+            vec![(2, 0), (1, 0), (0, 2)]
+        );
+        let times = |votes: &mut dyn Iterator<Item = &Vote>| -> Vec<usize> {
+            votes.map(|v| v.time).collect()
+        };
+        assert_eq!(times(&mut node.votes_expiring_within(2, 2)), vec![2, 1]);
+        for t in 0..10 {
+            let expiring = times(&mut node.votes_expiring_within(t, 0));
+            let expected: Vec<_> = node
+                .votes
+                .iter()
+                .filter(|v| v.is_active_at(t) && !v.is_active_at(t + 1))
+                .map(|v| v.time)
+                .collect();
+            assert_eq!(expiring, expected);
+        }
+
+        // a vote in its grace slots is still counting down
+        node.set_grace_slots(2);
+        assert_eq!(
+            node.lockout_expiry_countdown(6),
+            vec![(2, 0), (1, 1), (0, 4)]
+        );
+        assert_eq!(times(&mut node.votes_expiring_within(6, 0)), vec![1]);
+    }
+
+    #[test]
+    fn test_get_votes_before_after() {
+        let tree = BranchTree::default();
+        let bmap = ConvergeMap::default();
+        let b0 = Branch { id: 0, base: 0 };
+        let mut node = LockTower::new(32);
+        assert_eq!(node.get_votes_before(5).count(), 0);
+        assert_eq!(node.get_votes_after(5).count(), 0);
+        for time in 0..4 {
+            assert!(node.push_vote(Vote::new(b0.clone(), time), &tree, &bmap, 32));
+        }
+        let times = |votes: &mut dyn Iterator<Item = &Vote>| -> Vec<usize> {
+            votes.map(|v| v.time).collect()
+        };
+        let all = times(&mut node.get_votes_before(usize::MAX));
+        assert_eq!(all, vec![3, 2, 1, 0]);
+        assert_eq!(times(&mut node.get_votes_after(0)), vec![3, 2, 1]);
+        assert_eq!(times(&mut node.get_votes_before(2)), vec![1, 0]);
+        assert_eq!(times(&mut node.get_votes_after(2)), vec![3]);
+        for time in 0..5 {
+            let before = node.get_votes_before(time).count();
+            let at = node.vote_at_time(time).into_iter().count();
+            let total = before + at + node.get_votes_after(time).count();
+            assert_eq!(total, node.votes.len());
+        }
+    }
+
+    #[test]
+    fn test_converge_map() {
+        let mut map = ConvergeMap::default();
+        assert_eq!(map.get(1), 0);
+        map.increment(1);
+        map.increment(1);
+        assert_eq!(map.get(1), 2);
+        map.set(1, 50);
+        assert_eq!(map.get(1), 50);
+
+        let mut bmap = HashMap::new();
+        bmap.insert(0, 51);
+        let map = ConvergeMap::from_branch_map(&bmap);
+        assert_eq!(map.get(0), 51);
+
+        // the vote at depth 1 needs over 50% of the network
+        let tree = BranchTree::default();
+        let b0 = Branch { id: 0, base: 0 };
+        let mut node = LockTower::new(32);
+        assert!(node.push_vote(Vote::new(b0.clone(), 0), &tree, &map, 1));
+        assert!(node.push_vote(Vote::new(b0.clone(), 1), &tree, &map, 1));
+        let mut map = ConvergeMap::default();
+        map.set(0, 50);
+        assert!(!node.push_vote(Vote::new(b0.clone(), 2), &tree, &map, 1));
+        map.increment(0);
+        assert!(node.push_vote(Vote::new(b0.clone(), 2), &tree, &map, 1));
+    }
+
+    #[test]
+    fn test_compute_confidence() {
+        let tree = BranchTree::default();
+        let b0 = Branch { id: 0, base: 0 };
+        let mut map = ConvergeMap::default();
+        let mut node = LockTower::new(32);
+        assert_eq!(node.compute_confidence(&map, 100), 0);
+        for time in 0..4 {
+            assert!(node.push_vote(Vote::new(b0.clone(), time), &tree, &map, 32));
+        }
+        assert_eq!(node.compute_confidence(&map, 100), 0);
+        map.set(0, 50);
+        assert_eq!(node.compute_confidence(&map, 100), 50);
+        map.set(0, 100);
+        assert_eq!(node.compute_confidence(&map, 100), 100);
+    }
+
+    #[test]
+    fn test_threshold_depth() {
+        let tree = BranchTree::default();
+        let map = ConvergeMap::default();
+        let b0 = Branch { id: 0, base: 0 };
+        let mut node = LockTower::new(32);
+        assert_eq!(node.threshold_depth(), 0);
+        let mut last = 0;
+        for time in 0..20 {
+            assert!(node.push_vote(Vote::new(b0.clone(), time), &tree, &map, 32));
+            let depth = node.threshold_depth();
+            assert!(depth >= last);
+            if node.first_vote().unwrap().lockout < 1 << 8 {
+                assert_eq!(depth, node.votes.len());
+            } else {
+                assert_eq!(depth, node.votes.len() - 1);
+            }
+            last = depth;
+        }
+    }
+    #[test]
+    fn test_push_vote_threshold_depth() {
+        let tree = BranchTree::default();
+        let mut map = ConvergeMap::default();
+        let b0 = Branch { id: 0, base: 0 };
+        let mut node = LockTower::new(32);
+        node.set_threshold_lockout(4);
+        assert!(node.push_vote(Vote::new(b0.clone(), 0), &tree, &map, 0));
+        assert!(node.push_vote(Vote::new(b0.clone(), 1), &tree, &map, 0));
+        assert_eq!(node.threshold_depth(), 1);
+        let vote = Vote::new(b0.clone(), 2);
+        assert_eq!(
+            node.is_safe_to_vote(&vote, &tree, &map, 0),
+            Err(VoteError::NotConverged)
+        );
+        assert!(!node.push_vote(vote.clone(), &tree, &map, 0));
+        map.set(0, 51);
+        assert_eq!(node.is_safe_to_vote(&vote, &tree, &map, 0), Ok(()));
+        assert!(node.push_vote(vote, &tree, &map, 0));
+    }
+
+    #[test]
+    fn test_expected_lockout_at_depth() {
+        let expected: Vec<_> = (0..6)
+            .map(|d| Vote::expected_lockout_at_depth(d, 2, 2))
+            .collect();
+        assert_eq!(expected, vec![2, 4, 8, 16, 32, 64]);
+        assert_eq!(Vote::expected_lockout_at_depth(64, 2, 2), usize::MAX);
+        assert_eq!(Vote::expected_lockout_at_depth(3, 1, 1), 1);
+
+        let tree = BranchTree::default();
+        let map = ConvergeMap::default();
+        let b0 = Branch { id: 0, base: 0 };
+        let mut node = LockTower::new(32);
+        for time in 0..6 {
+            assert!(node.push_vote(Vote::new(b0.clone(), time), &tree, &map, 32));
+        }
+        for (d, v) in node.votes.iter().enumerate() {
+            assert_eq!(v.lockout, expected[d]);
+        }
+    }
+
+    #[test]
+    fn test_switch_threshold_check() {
+        let mut tree = BranchTree::default();
+        let b1 = Branch { id: 1, base: 0 };
+        let b2 = Branch { id: 2, base: 1 };
+        let b3 = Branch { id: 3, base: 1 };
+        let b4 = Branch { id: 4, base: 2 };
+        for b in &[&b1, &b2, &b3, &b4] {
+            tree.insert((*b).clone()).unwrap();
+        }
+        let map = ConvergeMap::default();
+        let mut node = LockTower::new(32);
+        assert!(node.push_vote(Vote::new(b1.clone(), 0), &tree, &map, 32));
+        assert!(node.push_vote(Vote::new(b2.clone(), 1), &tree, &map, 32));
+
+        let mut stake_map = HashMap::new();
+        stake_map.insert(1, 10);
+        stake_map.insert(3, 30);
+        stake_map.insert(4, 60);
+        assert_eq!(
+            node.switch_threshold_check(&b3, &tree, &stake_map, 100),
+            SwitchCheckResult::Blocked {
+                required: SWITCH_THRESHOLD,
+                actual: 0.3
+            }
+        );
+        // not a fork switch
+        assert_eq!(
+            node.switch_threshold_check(&b4, &tree, &stake_map, 100),
+            SwitchCheckResult::Allowed {
+                stake_fraction: 0.3
+            }
+        );
+        stake_map.insert(3, 40);
+        stake_map.insert(4, 50);
+        assert_eq!(
+            node.switch_threshold_check(&b3, &tree, &stake_map, 100),
+            SwitchCheckResult::Allowed {
+                stake_fraction: 0.4
+            }
+        );
+    }
+
+    #[test]
+    fn test_last_n_votes() {
+        let tree = BranchTree::default();
+        let map = ConvergeMap::default();
+        let b0 = Branch { id: 0, base: 0 };
+        let mut node = LockTower::new(32);
+        assert_eq!(node.vote_count(), 0);
+        assert_eq!(node.last_n_votes(2).count(), 0);
+        for time in 0..4 {
+            assert!(node.push_vote(Vote::new(b0.clone(), time), &tree, &map, 32));
+        }
+        assert_eq!(node.vote_count(), 4);
+        assert_eq!(node.last_n_votes(0).count(), 0);
+        let last: Vec<_> = node.last_n_votes(2).map(|v| (v.time, v.lockout)).collect();
+        assert_eq!(last, vec![(3, 2), (2, 4)]);
+        // the votes are borrowed from the tower
+        assert!(node
+            .last_n_votes(2)
+            .zip(node.votes.iter())
+            .all(|(a, b)| std::ptr::eq(a, b)));
+        assert_eq!(node.last_n_votes(10).count(), 4);
+    }
+
+    #[test]
+    fn test_branch_id_allocator() {
+        let mut allocator = BranchIdAllocator::new();
+        allocator.reserve(2);
+        let b1 = allocator.next(0);
+        let b3 = allocator.next(b1.id);
+        assert_eq!((b1.id, b1.base), (1, 0));
+        assert_eq!((b3.id, b3.base), (3, 1));
+    }
+    #[test]
+    fn test_concurrent_push_vote_cas() {
+        use std::sync::Arc;
+        use std::thread;
+        let mut tree = BranchTree::default();
+        let len = 200;
+        for id in 1..=len {
+            tree.insert(Branch { id, base: id - 1 }).unwrap();
+        }
+        let tree = Arc::new(tree);
+        let tower = Arc::new(ConcurrentLockTower::new(LockTower::new(32)));
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let tree = tree.clone();
+                let tower = tower.clone();
+                thread::spawn(move || {
+                    let map = ConvergeMap::default();
+                    let mut pushed = 0;
+                    loop {
+                        let last = tower.lock().last_branch();
+                        if last.id == len {
+                            return pushed;
+                        }
+                        let next = tree.get(last.id + 1).unwrap().clone();
+                        let vote = Vote::new(next, last.id);
+                        match tower.push_vote_cas(vote, last.id, &tree, &map, 32) {
+                            Ok(true) => pushed += 1,
+                            Ok(false) => (),
+                            Err(err) => panic!("unexpected {:?}", err),
+                        }
+                    }
+                })
+            }).collect();
+        let pushed: usize = handles.into_iter().map(|h| h.join().unwrap()).sum();
+        assert_eq!(pushed, len);
+        let tower = Arc::try_unwrap(tower).unwrap().into_inner();
+        assert_eq!(tower.last_branch().id, len);
+        assert_eq!(tower.total_votes_cast(), len);
+        assert!(tower.verify_invariants(&tree));
+    }
+
+    #[test]
+    fn test_branch_id_allocator_threads() {
+        use std::sync::Arc;
+        use std::thread;
+        let handles: Vec<_> = (0..2)
+            .map(|i| {
+                thread::spawn(move || {
+                    let mut allocator = BranchIdAllocator::with_start(1 + i * 1000);
+                    (0..1000).map(|_| allocator.next(0).id).collect::<Vec<_>>()
+                })
+            }).collect();
+        let mut ids = HashSet::new();
+        for h in handles {
+            for id in h.join().unwrap() {
+                assert!(ids.insert(id));
+            }
+        }
+        assert_eq!(ids.len(), 2000);
+
+        let allocator = Arc::new(AtomicBranchIdAllocator::new());
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let allocator = allocator.clone();
+                thread::spawn(move || (0..1000).map(|_| allocator.next(0).id).collect::<Vec<_>>())
+            }).collect();
+        let mut ids = HashSet::new();
+        for h in handles {
+            for id in h.join().unwrap() {
+                assert!(id > 0);
+                assert!(ids.insert(id));
+            }
+        }
+        assert_eq!(ids.len(), 4000);
+    }
+
+    #[test]
+    fn test_votes_iter() {
+        let tree = BranchTree::default();
+        let map = ConvergeMap::default();
+        let b0 = Branch { id: 0, base: 0 };
+        let mut node = LockTower::new(32);
+        assert_eq!(node.votes_iter().len(), 0);
+        for time in 0..4 {
+            assert!(node.push_vote(Vote::new(b0.clone(), time), &tree, &map, 32));
+        }
+        assert_eq!(node.votes_iter().len(), node.vote_count());
+        let times: Vec<_> = node.votes_iter().map(|v| v.time).collect();
+        assert_eq!(times, vec![3, 2, 1, 0]);
+        let lockouts: Vec<_> = node.votes_iter().rev().map(|v| v.lockout).collect();
+        assert_eq!(lockouts, vec![16, 8, 4, 2]);
+        assert_eq!(node.votes_iter().rev().len(), 4);
+
+        for v in node.votes_iter_mut().rev().take(1) {
+            let doubled = v.lockout() * 2;
+            v.set_lockout(doubled);
+        }
+        assert_eq!(node.first_vote().unwrap().lockout(), 32);
+        assert_eq!(node.votes_iter_mut().count(), 4);
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut tree = BranchTree::default();
+        let map = ConvergeMap::default();
+        let b0 = Branch { id: 0, base: 0 };
+        let b1 = Branch { id: 1, base: 0 };
+        let b2 = Branch { id: 2, base: 1 };
+        tree.insert(b1.clone()).unwrap();
+        tree.insert(b2.clone()).unwrap();
+        let mut node = LockTower::new(4);
+        node.set_threshold_lockout(4);
+        for time in 0..4 {
+            assert!(node.push_vote(Vote::new(b2.clone(), time), &tree, &map, 32));
+        }
+        assert_eq!(node.branch_trunk.id, 2);
+        node.reset();
+        assert_eq!(node.votes.len(), 0);
+        assert_eq!(node.last_branch().id, 0);
+        assert_eq!(node.max_size, 4);
+        assert_eq!(node.threshold_lockout, 4);
+
+        let mut fresh = LockTower::new(4);
+        for time in 10..14 {
+            let vote = Vote::new(b0.clone(), time);
+            assert!(fresh.push_vote(vote.clone(), &tree, &map, 32));
+            assert!(node.push_vote(vote, &tree, &map, 32));
+        }
+        let lockouts =
+            |n: &LockTower| -> Vec<usize> { n.votes.iter().map(|v| v.lockout).collect() };
+        assert_eq!(lockouts(&node), lockouts(&fresh));
+
+        node.reset_to(b1.clone());
+        assert_eq!(node.votes.len(), 0);
+        assert_eq!(node.branch_trunk.id, 1);
+        assert_eq!(node.last_branch().id, 1);
+        assert!(node.push_vote(Vote::new(b2.clone(), 20), &tree, &map, 32));
+        assert!(!node.push_vote(Vote::new(b0.clone(), 21), &tree, &map, 32));
+    }
+
+    #[test]
+    fn test_clone() {
+        let mut tree = BranchTree::default();
+        let map = ConvergeMap::default();
+        let b1 = Branch { id: 1, base: 0 };
+        let b2 = Branch { id: 2, base: 0 };
+        tree.insert(b1.clone()).unwrap();
+        tree.insert(b2.clone()).unwrap();
+        let mut node = LockTower::new(32);
+        for time in 0..4 {
+            assert!(node.push_vote(Vote::new(b1.clone(), time), &tree, &map, 32));
+        }
+        let mut clone = node.clone();
+        // all the votes on b1 have expired by time 20
+        assert!(clone.push_vote(Vote::new(b2.clone(), 20), &tree, &map, 32));
+        assert_eq!(clone.votes.len(), 1);
+        assert_eq!(clone.last_branch().id, 2);
+
+        assert_eq!(node.votes.len(), 4);
+        assert_eq!(node.last_branch().id, 1);
+        assert_eq!(node.votes[0].lockout, 2);
+        assert_eq!(node.votes[1].lockout, 4);
+        assert_eq!(node.votes[2].lockout, 8);
+        assert_eq!(node.votes[3].lockout, 16);
+    }
+
+    #[test]
+    fn test_slots_until_unlock() {
+        let mut tree = BranchTree::default();
+        let map = ConvergeMap::default();
+        let b1 = Branch { id: 1, base: 0 };
+        let b2 = Branch { id: 2, base: 1 };
+        let b3 = Branch { id: 3, base: 1 };
+        let b4 = Branch { id: 4, base: 2 };
+        for b in &[&b1, &b2, &b3, &b4] {
+            tree.insert((*b).clone()).unwrap();
+        }
+        let mut node = LockTower::new(32);
+        assert!(node.push_vote(Vote::new(b1.clone(), 0), &tree, &map, 32));
+        assert!(node.push_vote(Vote::new(b2.clone(), 1), &tree, &map, 32));
+        assert_eq!(node.slots_until_unlock(&b4, &tree, 1), None);
+        assert_eq!(node.slots_until_unlock(&b3, &tree, 1), Some(3));
+        assert_eq!(node.slots_until_unlock(&b3, &tree, 3), Some(1));
+        let vote = Vote::new(b3.clone(), 3);
+        assert!(!node.clone().push_vote(vote, &tree, &map, 32));
+        assert_eq!(node.slots_until_unlock(&b3, &tree, 4), None);
+        assert!(node.push_vote(Vote::new(b3.clone(), 4), &tree, &map, 32));
+    }
+
+    #[test]
+    fn test_push_vote_or_wait() {
+        let mut tree = BranchTree::default();
+        let b1 = Branch { id: 1, base: 0 };
+        let b2 = Branch { id: 2, base: 1 };
+        let b3 = Branch { id: 3, base: 1 };
+        let b4 = Branch { id: 4, base: 2 };
+        let b5 = Branch { id: 5, base: 0 };
+        for b in &[&b1, &b2, &b3, &b4, &b5] {
+            tree.insert((*b).clone()).unwrap();
+        }
+        let mut map = ConvergeMap::default();
+        let mut node = LockTower::new(32);
+        assert!(node.push_vote(Vote::new(b1.clone(), 0), &tree, &map, 32));
+        assert!(node.push_vote(Vote::new(b2.clone(), 1), &tree, &map, 32));
+        let vote = Vote::new(b4.clone(), 2);
+        let decision = node.push_vote_or_wait(&vote, &tree, &map, 32);
+        assert_eq!(decision, VotePushDecision::VoteNow);
+        // b2 at time 1 is locked until 3
+        let vote = Vote::new(b3.clone(), 2);
+        let decision = node.push_vote_or_wait(&vote, &tree, &map, 32);
+        assert_eq!(decision, VotePushDecision::WaitSlots(2));
+        // times the tower already voted at are never valid
+        let vote = Vote::new(b3.clone(), 1);
+        let decision = node.push_vote_or_wait(&vote, &tree, &map, 32);
+        assert_eq!(decision, VotePushDecision::AlreadyVoted);
+        assert!(!node.push_vote(vote, &tree, &map, 32));
+        let vote = Vote::new(b2.clone(), 1);
+        let decision = node.push_vote_or_wait(&vote, &tree, &map, 32);
+        assert_eq!(decision, VotePushDecision::AlreadyVoted);
+        let vote = Vote::new(b3.clone(), 4);
+        let decision = node.push_vote_or_wait(&vote, &tree, &map, 32);
+        assert_eq!(decision, VotePushDecision::VoteNow);
+        assert!(node.push_vote(vote, &tree, &map, 32));
+        let vote = Vote::new(b3.clone(), 3);
+        let decision = node.push_vote_or_wait(&vote, &tree, &map, 32);
+        assert_eq!(decision, VotePushDecision::AlreadyVoted);
+
+        let mut pending = LockTower::new(32);
+        assert!(pending.push_vote(Vote::new(b1.clone(), 0), &tree, &map, 32));
+        assert!(pending.push_vote(Vote::new(b2.clone(), 1), &tree, &map, 32));
+        map.set(1, 50);
+        let vote = Vote::new(b4.clone(), 2);
+        let decision = pending.push_vote_or_wait(&vote, &tree, &map, 1);
+        assert_eq!(decision, VotePushDecision::NotConverged);
+        assert!(!pending.push_vote(vote, &tree, &map, 1));
+
+        let mut rooted = LockTower::new(2);
+        assert!(rooted.push_vote(Vote::new(b1.clone(), 0), &tree, &map, 32));
+        assert!(rooted.push_vote(Vote::new(b2.clone(), 1), &tree, &map, 32));
+        let vote = Vote::new(b5.clone(), 10);
+        let decision = rooted.push_vote_or_wait(&vote, &tree, &map, 32);
+        assert_eq!(decision, VotePushDecision::InvalidBranch);
+
+        // a vote can be pushed now exactly when `is_safe_to_vote` allows it
+        for b in &[&b1, &b2, &b3, &b4, &b5] {
+            for time in 0..12 {
+                let vote = Vote::new((*b).clone(), time);
+                let decision = node.push_vote_or_wait(&vote, &tree, &map, 32);
+                let safe = node.is_safe_to_vote(&vote, &tree, &map, 32);
+                assert_eq!(decision == VotePushDecision::VoteNow, safe.is_ok());
+            }
+        }
+    }
+
+    #[test]
+    fn test_is_locked_for_branch() {
+        let mut tree = BranchTree::default();
+        let map = ConvergeMap::default();
+        let b1 = Branch { id: 1, base: 0 };
+        let b2 = Branch { id: 2, base: 1 };
+        let b3 = Branch { id: 3, base: 1 };
+        let b4 = Branch { id: 4, base: 2 };
+        for b in &[&b1, &b2, &b3, &b4] {
+            tree.insert((*b).clone()).unwrap();
+        }
+        let mut node = LockTower::new(32);
+        assert!(!node.is_locked_for_branch(&b3, &tree, 0));
+        assert!(node.push_vote(Vote::new(b1.clone(), 0), &tree, &map, 32));
+        assert!(node.push_vote(Vote::new(b2.clone(), 1), &tree, &map, 32));
+        assert!(!node.is_locked_for_branch(&b2, &tree, 2));
+        assert!(!node.is_locked_for_branch(&b4, &tree, 2));
+        // an ancestor doesn't descend from the last vote either
+        assert!(node.is_locked_for_branch(&b1, &tree, 2));
+        assert!(node.is_locked_for_branch(&b3, &tree, 2));
+        assert!(node.is_locked_for_branch(&b3, &tree, 3));
+        assert!(!node.is_locked_for_branch(&b3, &tree, 4));
+        assert!(node.push_vote(Vote::new(b3.clone(), 4), &tree, &map, 32));
+    }
+
+    #[test]
+    fn test_grace_slots() {
+        let mut tree = BranchTree::default();
+        let map = ConvergeMap::default();
+        let b1 = Branch { id: 1, base: 0 };
+        let b2 = Branch { id: 2, base: 0 };
+        tree.insert(b1.clone()).unwrap();
+        tree.insert(b2.clone()).unwrap();
+        let mut node = LockTower::new(32);
+        node.set_grace_slots(2);
+        assert!(node.push_vote(Vote::new(b1.clone(), 3), &tree, &map, 32));
+        assert_eq!(node.votes[0].lock_height(), 5);
+        assert_eq!(node.slots_until_unlock(&b2, &tree, 6), Some(2));
+        assert!(!node.push_vote(Vote::new(b2.clone(), 6), &tree, &map, 32));
+        assert_eq!(node.votes.len(), 1);
+        assert_eq!(node.slots_until_unlock(&b2, &tree, 8), None);
+        assert!(node.push_vote(Vote::new(b2.clone(), 8), &tree, &map, 32));
+        assert_eq!(node.votes.len(), 1);
+        assert_eq!(node.last_branch().id, 2);
+    }
+
+    #[test]
+    fn test_vote_at_time() {
+        let mut tree = BranchTree::default();
+        let map = ConvergeMap::default();
+        let b1 = Branch { id: 1, base: 0 };
+        let b2 = Branch { id: 2, base: 1 };
+        tree.insert(b1.clone()).unwrap();
+        tree.insert(b2.clone()).unwrap();
+        let mut node = LockTower::new(32);
+        assert!(node.vote_at_time(0).is_none());
+        assert!(node.push_vote(Vote::new(b1.clone(), 0), &tree, &map, 32));
+        assert!(node.push_vote(Vote::new(b2.clone(), 1), &tree, &map, 32));
+        assert!(node.vote_at_time(2).is_none());
+        assert_eq!(node.vote_at_time(0).unwrap().branch.id, 1);
+        assert_eq!(node.vote_at_time(1).unwrap().branch.id, 2);
+
+        // the vote at time 1 expires at time 3
+        assert!(node.push_vote(Vote::new(b1.clone(), 4), &tree, &map, 32));
+        assert!(node.vote_at_time(1).is_none());
+        assert_eq!(node.vote_at_time(4).unwrap().branch.id, 1);
+    }
+
+    #[test]
+    fn test_repair_lockouts() {
+        let mut tree = BranchTree::default();
+        let map = ConvergeMap::default();
+        let b1 = Branch { id: 1, base: 0 };
+        tree.insert(b1.clone()).unwrap();
+        let mut node = LockTower::new(32);
+        for time in 0..4 {
+            assert!(node.push_vote(Vote::new(b1.clone(), time), &tree, &map, 32));
+        }
+        assert_eq!(node.repair_lockouts(), 0);
+        let expected: Vec<_> = node.votes_iter().map(|v| v.lockout).collect();
+        node.votes_iter_mut().nth(2).unwrap().lockout = 3;
+        assert!(!node.verify_invariants(&tree));
+        assert_eq!(node.repair_lockouts(), 1);
+        let repaired: Vec<_> = node.votes_iter().map(|v| v.lockout).collect();
+        assert_eq!(repaired, expected);
+        assert_eq!(repaired[2], 8);
+        assert!(node.verify_invariants(&tree));
+
+        // only the corrupted lockouts are replaced
+        node.votes_iter_mut().nth(1).unwrap().lockout = 5;
+        assert_eq!(node.repair_lockouts(), 1);
+        let repaired: Vec<_> = node.votes_iter().map(|v| v.lockout).collect();
+        assert_eq!(repaired, expected);
+        node.votes_iter_mut().nth(1).unwrap().lockout = 2;
+        node.votes_iter_mut().nth(3).unwrap().lockout = 20;
+        assert_eq!(node.repair_lockouts(), 2);
+        let repaired: Vec<_> = node.votes_iter().map(|v| v.lockout).collect();
+        assert_eq!(repaired, expected);
+
+        // a tower that survived a rollback is left as it is
+        let mut node = LockTower::new(32);
+        for time in 0..4 {
+            assert!(node.push_vote(Vote::new(b1.clone(), time), &tree, &map, 32));
+        }
+        assert!(node.push_vote(Vote::new(b1.clone(), 10), &tree, &map, 32));
+        let lockouts: Vec<_> = node.votes_iter().map(|v| v.lockout).collect();
+        assert_eq!(lockouts, vec![2, 16]);
+        assert_eq!(node.repair_lockouts(), 0);
+        let repaired: Vec<_> = node.votes_iter().map(|v| v.lockout).collect();
+        assert_eq!(repaired, lockouts);
+    }
+
+    #[test]
+    fn test_confirmations_needed() {
+        let tree = BranchTree::default();
+        let map = ConvergeMap::default();
+        let b0 = Branch { id: 0, base: 0 };
+        let mut node = LockTower::new(32);
+        assert_eq!(node.confirmations_needed(2), Some(1));
+        assert_eq!(node.confirmations_needed(16), Some(4));
+        for time in 0..4 {
+            assert!(node.push_vote(Vote::new(b0.clone(), time), &tree, &map, 32));
+        }
+        assert_eq!(node.confirmations_needed(16), Some(0));
+        assert_eq!(node.confirmations_needed(32), Some(1));
+        assert_eq!(node.confirmations_needed(64), Some(2));
+
+        // after rollback the lockouts are not doubled until the stack catches up
+        assert!(node.push_vote(Vote::new(b0.clone(), 10), &tree, &map, 32));
+        let lockouts: Vec<_> = node.votes.iter().map(|v| v.lockout).collect();
+        assert_eq!(lockouts, vec![2, 16]);
+        let needed = node.confirmations_needed(32).unwrap();
+        for time in 11..(11 + needed) {
+            assert_ne!(node.first_vote().unwrap().lockout, 32);
+            assert!(node.push_vote(Vote::new(b0.clone(), time), &tree, &map, 32));
+        }
+        assert_eq!(node.first_vote().unwrap().lockout, 32);
+
+        // the doubling saturates, a schedule that doesn't grow never reaches the lockout
+        assert!(node.confirmations_needed(usize::MAX).is_some());
+        node.set_lockout_schedule(Box::new(CustomSchedule(|lockout, _| lockout)));
+        assert_eq!(node.confirmations_needed(64), None);
+        assert_eq!(node.next_safe_slot_in(64), None);
+        let mut fibonacci = LockTower::new(32);
+        fibonacci.set_lockout_schedule(Box::new(FibonacciSchedule));
+        assert!(fibonacci.confirmations_needed(usize::MAX).is_some());
+    }
+
+    #[test]
+    fn test_slots_until_expiry() {
+        let vote = Vote::new(Branch::default(), 0);
+        assert_eq!(vote.slots_until_expiry(0), Some(2));
+        assert!(vote.is_active_at(0));
+        assert_eq!(vote.slots_until_expiry(1), Some(1));
+        assert!(vote.is_active_at(1));
+        assert_eq!(vote.slots_until_expiry(vote.lock_height()), Some(0));
+        assert!(!vote.is_active_at(vote.lock_height()));
+        assert_eq!(vote.slots_until_expiry(3), None);
+        assert!(!vote.is_active_at(3));
+    }
+
+    #[test]
+    fn test_lockout_schedule() {
+        let tree = BranchTree::default();
+        let map = ConvergeMap::default();
+        let b0 = Branch { id: 0, base: 0 };
+        let lockouts = |schedule: Option<Box<dyn LockoutSchedule>>| -> Vec<usize> {
+            let mut node = LockTower::new(32);
+            if let Some(schedule) = schedule {
+                node.set_lockout_schedule(schedule);
+            }
+            for time in 0..6 {
+                assert!(node.push_vote(Vote::new(b0.clone(), time), &tree, &map, 32));
+            }
+            assert!(node.verify_invariants(&tree));
+            assert_eq!(node.clone().export_votes(), node.export_votes());
+            node.votes.iter().map(|v| v.lockout).collect()
+        };
+        assert_eq!(lockouts(None), vec![2, 4, 8, 16, 32, 64]);
+        assert_eq!(
+            lockouts(Some(Box::new(DoublingSchedule))),
+            vec![2, 4, 8, 16, 32, 64]
+        );
+        assert_eq!(
+            lockouts(Some(Box::new(FibonacciSchedule))),
+            vec![2, 3, 5, 8, 13, 21]
+        );
+        assert_eq!(
+            lockouts(Some(Box::new(CustomSchedule(|lockout, _| lockout * 3)))),
+            vec![2, 6, 18, 54, 162, 486]
+        );
+
+        let mut node = LockTower::new(32);
+        node.set_lockout_schedule(Box::new(FibonacciSchedule));
+        assert_eq!(node.confirmations_needed(21), Some(6));
+    }
+
+    #[test]
+    fn test_restore_with_schedule() {
+        let mut tree = BranchTree::default();
+        let map = ConvergeMap::default();
+        let b1 = Branch { id: 1, base: 0 };
+        tree.insert(b1.clone()).unwrap();
+        let mut node = LockTower::new(32);
+        node.set_lockout_schedule(Box::new(FibonacciSchedule));
+        for time in 0..5 {
+            assert!(node.push_vote(Vote::new(b1.clone(), time), &tree, &map, 32));
+        }
+        let proof = node.tower_proof();
+        let mut restored = LockTower::new(32);
+        restored.set_lockout_schedule(Box::new(FibonacciSchedule));
+        assert_eq!(restored.catchup_from_proof(&proof, &tree), Ok(()));
+        assert_eq!(restored.export_votes(), node.export_votes());
+        let mut doubling = LockTower::new(32);
+        let restore = doubling.catchup_from_proof(&proof, &tree);
+        assert_eq!(restore, Err(CatchupError::InvalidSchedule));
+
+        let vote = Vote::new(b1.clone(), 5);
+        let verified =
+            LockTower::verify_tower_proof_with_schedule(&proof, &vote, &tree, &FibonacciSchedule);
+        assert_eq!(verified, Ok(()));
+        let verified = LockTower::verify_tower_proof(&proof, &vote, &tree);
+        assert_eq!(verified, Err(ProofError::InvalidLockout));
+
+        let exported = node.export_votes();
+        let schedule = Box::new(FibonacciSchedule);
+        let imported = LockTower::import_votes_with_schedule(&exported, 0, 32, &tree, schedule);
+        let mut imported = imported.unwrap();
+        assert_eq!(imported.export_votes(), exported);
+        assert!(imported.push_vote(vote.clone(), &tree, &map, 32));
+        assert!(node.push_vote(vote, &tree, &map, 32));
+        assert_eq!(imported.export_votes(), node.export_votes());
+        assert!(LockTower::import_votes(&exported, 0, 32, &tree).is_err());
+    }
+
+    #[test]
+    fn test_epoch_votes() {
+        let tree = BranchTree::default();
+        let map = ConvergeMap::default();
+        let b0 = Branch { id: 0, base: 0 };
+        let mut node = LockTower::new(60);
+        // a vote every other slot doesn't roll back the newest vote
+        for time in (0..100).step_by(2) {
+            assert!(node.push_vote(Vote::new(b0.clone(), time), &tree, &map, 60));
+        }
+        assert_eq!(node.votes.len(), 50);
+        let slot_to_epoch = |slot| slot as u64 / 32;
+        let epochs = node.epoch_votes(slot_to_epoch);
+        assert_eq!(epochs.len(), 4);
+        for epoch in 0..3 {
+            assert_eq!(epochs[&epoch].len(), 16);
+        }
+        let times: Vec<_> = epochs[&3].iter().map(|v| v.time).collect();
+        assert_eq!(times, vec![98, 96]);
+        assert!(epochs[&1].iter().all(|v| v.time >= 32 && v.time < 64));
+
+        let times: Vec<_> = node
+            .votes_in_epoch(3, slot_to_epoch)
+            .map(|v| v.time)
+            .collect();
+        assert_eq!(times, vec![98, 96]);
+        assert_eq!(node.votes_in_epoch(4, slot_to_epoch).count(), 0);
+    }
+
+    #[test]
+    fn test_apply_votes_from_gossip() {
+        let mut tree = BranchTree::default();
+        let map = ConvergeMap::default();
+        let b1 = Branch { id: 1, base: 0 };
+        let b2 = Branch { id: 2, base: 1 };
+        let b3 = Branch { id: 3, base: 0 };
+        tree.insert(b1.clone()).unwrap();
+        tree.insert(b2.clone()).unwrap();
+        tree.insert(b3.clone()).unwrap();
+
+        let vote = Vote::new(b1.clone(), 0);
+        let mut node = LockTower::new(32);
+        let incoming = vec![vote.clone(); 5];
+        let result = node.apply_votes_from_gossip(&incoming, &tree, &map, 32);
+        assert_eq!(result.applied, 1);
+        assert_eq!(result.deduplicated, 4);
+
+        let mut single = LockTower::new(32);
+        assert!(single.push_vote(vote, &tree, &map, 32));
+        assert_eq!(node.votes.len(), single.votes.len());
+        assert_eq!(node.votes[0].lockout, single.votes[0].lockout);
+        assert_eq!(node.last_branch().id, single.last_branch().id);
+
+        let incoming = vec![
+            Vote::new(b2.clone(), 1),
+            Vote::new(b3.clone(), 2),
+            Vote::new(b1.clone(), 0),
+            Vote::new(b2.clone(), 2),
+        ];
+        let result = node.apply_votes_from_gossip(&incoming, &tree, &map, 1);
+        assert_eq!(
+            result,
+            GossipApplyResult {
+                applied: 1,
+                rejected_branch: 1,
+                rejected_convergence: 1,
+                rejected_cooldown: 0,
+                rejected_old: 0,
+                deduplicated: 1,
+            }
+        );
+        let result = node.apply_votes_from_gossip(&[Vote::new(b2.clone(), 0)], &tree, &map, 32);
+        assert_eq!(result.rejected_old, 1);
+
+        // only the votes still in the tower are remembered
+        assert_eq!(node.gossip_seen.len(), 2);
+        node.pop_expired_votes(100);
+        assert!(node.gossip_seen.is_empty());
+        let mut small = LockTower::new(2);
+        let incoming = [Vote::new(b1.clone(), 0), Vote::new(b2.clone(), 1)];
+        let result = small.apply_votes_from_gossip(&incoming, &tree, &map, 32);
+        assert_eq!(result.applied, 2);
+        assert_eq!(small.gossip_seen.len(), 1);
+
+        // votes gossiped from a deep tower carry its lockouts
+        let mut deep = LockTower::new(32);
+        for time in 0..5 {
+            assert!(deep.push_vote(Vote::new(b2.clone(), time), &tree, &map, 32));
+        }
+        let mut incoming: Vec<_> = deep.votes_iter().cloned().collect();
+        incoming.reverse();
+        let mut node = LockTower::new(32);
+        let result = node.apply_votes_from_gossip(&incoming, &tree, &map, 32);
+        assert_eq!(result.applied, 5);
+        assert_eq!(node.export_votes(), deep.export_votes());
+    }
+
+    #[test]
+    fn test_coverage_fraction() {
+        let mut tree = BranchTree::default();
+        let map = ConvergeMap::default();
+        let b1 = Branch { id: 1, base: 0 };
+        tree.insert(b1.clone()).unwrap();
+        let mut node = LockTower::new(32);
+        assert_eq!(node.coverage_fraction(0, 9), 0.0);
+
+        // covers [10, 12)
+        assert!(node.push_vote(Vote::new(b1.clone(), 10), &tree, &map, 32));
+        assert_eq!(node.coverage_fraction(10, 11), 1.0);
+        assert_eq!(node.coverage_fraction(10, 13), 0.5);
+        assert_eq!(node.coverage_fraction(0, 9), 0.0);
+
+        // push_vote would roll back the older vote, so build the votes directly
+        // [10, 14) and [20, 22)
+        node.votes[0].lockout = 4;
+        node.votes.push_front(Vote::new(b1.clone(), 20));
+        assert_eq!(node.coverage_fraction(10, 29), 6.0 / 20.0);
+        assert_eq!(node.coverage_fraction(20, 21), 1.0);
+        assert_eq!(node.coverage_fraction(14, 19), 0.0);
+    }
+
+    #[test]
+    fn test_finalized_branches() {
+        let mut tree = BranchTree::default();
+        let map = ConvergeMap::default();
+        let mut node = LockTower::new(4);
+        let mut branches = vec![];
+        for time in 0..6 {
+            let branch = Branch {
+                id: time + 1,
+                base: time,
+            };
+            tree.insert(branch.clone()).unwrap();
+            branches.push(branch.clone());
+            assert!(node.push_vote(Vote::new(branch, time), &tree, &map, 32));
+        }
+        let finalized: Vec<_> = node.finalized_branches().map(|b| b.id).collect();
+        assert_eq!(finalized, vec![1, 2, 3]);
+        assert_eq!(node.branch_trunk.id, 3);
+        assert!(node.is_finalized(&branches[0], &tree));
+        assert!(node.is_finalized(&branches[2], &tree));
+        assert!(!node.is_finalized(&branches[3], &tree));
+        assert!(!node.is_finalized(&Branch { id: 7, base: 0 }, &tree));
+    }
+
+    #[test]
+    fn test_tower_proof() {
+        let mut tree = BranchTree::default();
+        let map = ConvergeMap::default();
+        let b1 = Branch { id: 1, base: 0 };
+        let b2 = Branch { id: 2, base: 1 };
+        let b3 = Branch { id: 3, base: 2 };
+        let b4 = Branch { id: 4, base: 1 };
+        for b in &[&b1, &b2, &b3, &b4] {
+            tree.insert((*b).clone()).unwrap();
+        }
+        let mut node = LockTower::new(32);
+        assert!(node.push_vote(Vote::new(b1.clone(), 0), &tree, &map, 32));
+        assert!(node.push_vote(Vote::new(b2.clone(), 1), &tree, &map, 32));
+        assert!(node.push_vote(Vote::new(b2.clone(), 2), &tree, &map, 32));
+        let proof = node.tower_proof();
+        assert_eq!(proof.votes, vec![(2, 2), (2, 4), (1, 8)]);
+        assert_eq!(proof.branch_trunk, 0);
+
+        let vote = Vote::new(b3.clone(), 3);
+        assert_eq!(LockTower::verify_tower_proof(&proof, &vote, &tree), Ok(()));
+        let empty = LockTower::new(32).tower_proof();
+        assert_eq!(LockTower::verify_tower_proof(&empty, &vote, &tree), Ok(()));
+
+        let mut tampered = proof.clone();
+        tampered.votes[1].1 = 16;
+        tampered.votes[2].1 = 16;
+        assert_eq!(
+            LockTower::verify_tower_proof(&tampered, &vote, &tree),
+            Err(ProofError::InvalidLockout)
+        );
+        tampered.votes[2].1 = 12;
+        assert_eq!(
+            LockTower::verify_tower_proof(&tampered, &vote, &tree),
+            Err(ProofError::InvalidLockout)
+        );
+
+        let vote = Vote::new(b4.clone(), 3);
+        assert_eq!(
+            LockTower::verify_tower_proof(&proof, &vote, &tree),
+            Err(ProofError::InvalidBranch)
+        );
+    }
+
+    #[test]
+    fn test_max_min_lockout() {
+        let tree = BranchTree::default();
+        let map = ConvergeMap::default();
+        let b0 = Branch { id: 0, base: 0 };
+        let mut node = LockTower::new(32);
+        assert_eq!(node.max_lockout(), None);
+        assert_eq!(node.min_lockout(), None);
+        assert!(node.push_vote(Vote::new(b0.clone(), 0), &tree, &map, 32));
+        assert_eq!(node.max_lockout(), Some(2));
+        assert_eq!(node.min_lockout(), Some(2));
+        for time in 1..4 {
+            assert!(node.push_vote(Vote::new(b0.clone(), time), &tree, &map, 32));
+        }
+        assert_eq!(node.max_lockout(), Some(16));
+        assert_eq!(node.min_lockout(), Some(2));
+    }
+
+    #[test]
+    fn test_first_unrooted_vote() {
+        let mut tree = BranchTree::default();
+        let map = ConvergeMap::default();
+        let b1 = Branch { id: 1, base: 0 };
+        tree.insert(b1.clone()).unwrap();
+        let mut node = LockTower::new(3);
+        assert!(node.first_unrooted_vote().is_none());
+        assert_eq!(node.unrooted_vote_count(), 0);
+        for time in 0..5 {
+            assert!(node.push_vote(Vote::new(b1.clone(), time), &tree, &map, 32));
+            let first = node.first_unrooted_vote().unwrap();
+            assert_eq!(first.time, node.votes.back().unwrap().time);
+            let max = node.votes.iter().map(|v| v.lockout).max().unwrap();
+            assert_eq!(first.lockout, max);
+            assert_eq!(node.unrooted_vote_count(), node.votes.len());
+        }
+        assert_eq!(node.unrooted_vote_count(), 2);
+        assert_eq!(node.first_unrooted_vote().unwrap().time, 3);
+    }
+
+    #[test]
+    fn test_oldest_active_vote() {
+        let tree = BranchTree::default();
+        let map = ConvergeMap::default();
+        let b0 = Branch { id: 0, base: 0 };
+        let mut node = LockTower::new(32);
+        assert!(node.oldest_active_vote(0).is_none());
+        for time in 0..4 {
+            assert!(node.push_vote(Vote::new(b0.clone(), time), &tree, &map, 32));
+        }
+        // lock heights are 5, 6, 9 and 16
+        assert_eq!(node.oldest_active_vote(4).unwrap().time, 0);
+        assert_eq!(node.oldest_active_vote(15).unwrap().time, 0);
+        assert!(node.oldest_active_vote(16).is_none());
+
+        // a newer vote can outlive an older one
+        node.votes[3].lockout = 4;
+        assert_eq!(node.oldest_active_vote(4).unwrap().time, 1);
+        assert_eq!(node.oldest_active_vote(6).unwrap().time, 1);
+        assert_eq!(node.oldest_active_vote(8).unwrap().time, 1);
+        assert!(node.oldest_active_vote(9).is_none());
+
+        // the grace slots keep the votes active as long as rollback keeps them
+        node.set_grace_slots(2);
+        assert_eq!(node.oldest_active_vote(10).unwrap().time, 1);
+        assert!(node.oldest_active_vote(11).is_none());
+        assert_eq!(node.votes_expiring_within(9, 1).count(), 1);
+    }
+
+    #[test]
+    fn test_pop_expired_votes() {
+        let tree = BranchTree::default();
+        let map = ConvergeMap::default();
+        let b0 = Branch { id: 0, base: 0 };
+        let mut node = LockTower::new(32);
+        for time in 0..4 {
+            assert!(node.push_vote(Vote::new(b0.clone(), time), &tree, &map, 32));
+        }
+        // lock heights are 5, 6, 9 and 16
+        assert!(node.pop_expired_votes(5).is_empty());
+        let popped: Vec<_> = node.pop_expired_votes(7).iter().map(|v| v.time).collect();
+        assert_eq!(popped, vec![2, 3]);
+        assert!(node.pop_expired_votes(7).is_empty());
+        assert_eq!(node.votes.len(), 2);
+
+        let popped: Vec<_> = node.pop_expired_votes(17).iter().map(|v| v.time).collect();
+        assert_eq!(popped, vec![0, 1]);
+        assert!(node.votes.is_empty());
+        assert!(node.pop_expired_votes(17).is_empty());
+    }
+
+    #[test]
+    fn test_export_import_votes() {
+        let mut tree = BranchTree::default();
+        let map = ConvergeMap::default();
+        let b1 = Branch { id: 1, base: 0 };
+        let b2 = Branch { id: 2, base: 1 };
+        let b3 = Branch { id: 3, base: 0 };
+        tree.insert(b1.clone()).unwrap();
+        tree.insert(b2.clone()).unwrap();
+        tree.insert(b3.clone()).unwrap();
+        let mut node = LockTower::new(4);
+        for (time, b) in [&b1, &b1, &b2, &b2, &b2].iter().enumerate() {
+            assert!(node.push_vote(Vote::new((*b).clone(), time), &tree, &map, 4));
+        }
+        let exported = node.export_votes();
+        assert_eq!(exported, vec![(4, 2, 2), (3, 2, 4), (2, 2, 8)]);
+        let imported = LockTower::import_votes(&exported, 1, 4, &tree).unwrap();
+        assert_eq!(imported.export_votes(), exported);
+        assert_eq!(imported.branch_trunk.id, 1);
+        // the imported times are already voted
+        let mut imported = imported;
+        assert_eq!(
+            imported.try_push_vote(Vote::new(b2.clone(), 4), &tree, &map, 4),
+            Err(VoteError::DoubleVote { time: 4 })
+        );
+        assert_eq!(imported.export_votes(), exported);
+
+        let mut tampered = exported.clone();
+        tampered[0].0 = 1;
+        assert_eq!(
+            LockTower::import_votes(&tampered, 1, 4, &tree).unwrap_err(),
+            ImportError::NotDecreasingTime { index: 1 }
+        );
+        let mut tampered = exported.clone();
+        tampered[1].2 = 8;
+        assert_eq!(
+            LockTower::import_votes(&tampered, 1, 4, &tree).unwrap_err(),
+            ImportError::InvalidLockout
+        );
+        let mut tampered = exported.clone();
+        tampered[1].1 = 3;
+        assert_eq!(
+            LockTower::import_votes(&tampered, 1, 4, &tree).unwrap_err(),
+            ImportError::InvalidBranch { index: 1 }
+        );
+        assert_eq!(
+            LockTower::import_votes(&exported, 3, 4, &tree).unwrap_err(),
+            ImportError::InvalidBranch { index: 2 }
+        );
+        assert_eq!(
+            LockTower::import_votes(&exported, 1, 3, &tree).unwrap_err(),
+            ImportError::TooManyVotes {
+                len: 3,
+                max_size: 3
+            }
+        );
+    }
+
+    #[test]
+    fn test_safe_slot() {
+        let tree = BranchTree::default();
+        let map = ConvergeMap::default();
+        let b0 = Branch { id: 0, base: 0 };
+        let mut node = LockTower::new(32);
+        assert_eq!(node.safe_slot(2), None);
+        assert_eq!(node.next_safe_slot_in(16), Some(4));
+        for time in 0..4 {
+            assert!(node.push_vote(Vote::new(b0.clone(), time), &tree, &map, 32));
+        }
+        assert_eq!(node.first_vote().unwrap().lockout, 16);
+        assert_eq!(node.safe_slot(16), Some(0));
+        assert_eq!(node.safe_slot(32), None);
+        assert_eq!(node.next_safe_slot_in(16), Some(0));
+        assert_eq!(node.next_safe_slot_in(32), Some(1));
+
+        // the first vote of a 4 deep tower is popped before its lockout reaches 16
+        let node = LockTower::new(4);
+        assert_eq!(node.next_safe_slot_in(8), Some(3));
+        assert_eq!(node.next_safe_slot_in(16), None);
+    }
+
+    #[test]
+    fn test_phase() {
+        let tree = BranchTree::default();
+        let map = ConvergeMap::default();
+        let b0 = Branch { id: 0, base: 0 };
+        let mut node = LockTower::new(4);
+        let mut phases = vec![node.phase()];
+        for time in 0..5 {
+            assert!(node.push_vote(Vote::new(b0.clone(), time), &tree, &map, 4));
+            phases.push(node.phase());
+        }
+        let transitions = phases.windows(2).filter(|p| p[0] != p[1]).count();
+        assert_eq!(transitions, 1);
+        assert_eq!(phases[3], TowerPhase::Warmup);
+        assert_eq!(phases[4], TowerPhase::SteadyState);
+
+        // rolling back the whole tower doesn't go back to warmup
+        assert!(node.push_vote(Vote::new(b0.clone(), 100), &tree, &map, 4));
+        assert_eq!(node.votes.len(), 1);
+        assert_eq!(node.phase(), TowerPhase::SteadyState);
+
+        node.reset();
+        assert_eq!(node.phase(), TowerPhase::Warmup);
+    }
+
+    #[test]
+    fn test_rebase() {
+        let mut tree = BranchTree::default();
+        let map = ConvergeMap::default();
+        let b1 = Branch { id: 1, base: 0 };
+        let b2 = Branch { id: 2, base: 1 };
+        let b3 = Branch { id: 3, base: 2 };
+        let b4 = Branch { id: 4, base: 0 };
+        tree.insert(b1.clone()).unwrap();
+        tree.insert(b2.clone()).unwrap();
+        tree.insert(b3.clone()).unwrap();
+        tree.insert(b4.clone()).unwrap();
+        let mut node = LockTower::new(32);
+        for (time, b) in [&b1, &b2, &b2, &b3].iter().enumerate() {
+            assert!(node.push_vote(Vote::new((*b).clone(), time), &tree, &map, 32));
+        }
+        assert_eq!(node.rebase(b2.clone(), &tree), Ok(()));
+        assert_eq!(node.branch_trunk.id, 2);
+        let ids: Vec<_> = node.votes.iter().map(|v| v.branch.id).collect();
+        assert_eq!(ids, vec![3]);
+        assert_eq!(node.last_branch().id, 3);
+        assert!(node.push_vote(Vote::new(b3.clone(), 4), &tree, &map, 32));
+        for b in &[&b1, &b4] {
+            let err = node.rebase((*b).clone(), &tree);
+            assert_eq!(err, Err(RebaseError::NotDescendant));
+        }
+        assert_eq!(node.votes.len(), 2);
+
+        // the network rooted a branch the tower has no vote for
+        assert_eq!(node.rebase(b3.clone(), &tree), Ok(()));
+        assert!(node.votes.is_empty());
+        assert_eq!(node.last_branch().id, 3);
+        assert!(node.push_vote(Vote::new(b3.clone(), 5), &tree, &map, 32));
+    }
+
+    #[test]
+    fn test_mark_finalized() {
+        let mut tree = BranchTree::default();
+        let map = ConvergeMap::default();
+        let b1 = Branch { id: 1, base: 0 };
+        let b2 = Branch { id: 2, base: 1 };
+        let b3 = Branch { id: 3, base: 1 };
+        let b4 = Branch { id: 4, base: 2 };
+        tree.insert(b1.clone()).unwrap();
+        tree.insert(b2.clone()).unwrap();
+        tree.insert(b3.clone()).unwrap();
+        tree.insert(b4.clone()).unwrap();
+        assert!(tree.finalized().is_none());
+        assert_eq!(
+            tree.mark_finalized(Branch { id: 5, base: 4 }),
+            Err(BranchTreeError::NotFound { id: 5 })
+        );
+
+        tree.mark_finalized(b2.clone()).unwrap();
+        assert_eq!(tree.finalized().unwrap().id, 2);
+        let node = LockTower::new(32);
+        assert_eq!(
+            node.is_safe_to_vote(&Vote::new(b3.clone(), 0), &tree, &map, 32),
+            Err(VoteError::BelowFinalized)
+        );
+        let mut node = LockTower::new(32);
+        assert!(!node.push_vote(Vote::new(b3.clone(), 0), &tree, &map, 32));
+        assert!(!node.push_vote(Vote::new(b1.clone(), 0), &tree, &map, 32));
+        assert!(node.push_vote(Vote::new(b2.clone(), 0), &tree, &map, 32));
+        assert!(node.push_vote(Vote::new(b4.clone(), 1), &tree, &map, 32));
+
+        // the frontier only moves forward
+        tree.mark_finalized(b1.clone()).unwrap();
+        assert_eq!(tree.finalized().unwrap().id, 2);
+        assert_eq!(
+            tree.mark_finalized(b3.clone()),
+            Err(BranchTreeError::NotDescendant { id: 3 })
+        );
+        tree.mark_finalized(b4.clone()).unwrap();
+        assert_eq!(tree.finalized().unwrap().id, 4);
+        tree.mark_finalized(b2.clone()).unwrap();
+        assert_eq!(tree.finalized().unwrap().id, 4);
+    }
+
+    #[test]
+    fn test_double_vote() {
+        let mut tree = BranchTree::default();
+        let map = ConvergeMap::default();
+        let b1 = Branch { id: 1, base: 0 };
+        let b2 = Branch { id: 2, base: 0 };
+        let b3 = Branch { id: 3, base: 1 };
+        tree.insert(b1.clone()).unwrap();
+        tree.insert(b2.clone()).unwrap();
+        tree.insert(b3.clone()).unwrap();
+        let mut node = LockTower::new(32);
+        assert_eq!(
+            node.try_push_vote(Vote::new(b1.clone(), 5), &tree, &map, 32),
+            Ok(())
+        );
+        assert!(node.double_vote_proof(5).is_none());
+        assert_eq!(
+            node.is_safe_to_vote(&Vote::new(b3.clone(), 5), &tree, &map, 32),
+            Err(VoteError::DoubleVote { time: 5 })
+        );
+        // pushing the same vote again is not an equivocation
+        assert_eq!(
+            node.try_push_vote(Vote::new(b1.clone(), 5), &tree, &map, 32),
+            Err(VoteError::DoubleVote { time: 5 })
+        );
+        assert!(node.double_vote_proof(5).is_none());
+        let double = Vote::new(b2.clone(), 5);
+        assert_eq!(
+            node.try_push_vote(double, &tree, &map, 32),
+            Err(VoteError::DoubleVote { time: 5 })
+        );
+        assert_eq!(node.votes.len(), 1);
+        let (existing, attempted) = node.double_vote_proof(5).unwrap();
+        assert_eq!(existing.branch.id, 1);
+        assert_eq!(attempted.branch.id, 2);
+        assert_eq!(existing.time, attempted.time);
+
+        // the time stays used after the vote is rolled back, the proof is dropped
+        assert!(node.push_vote(Vote::new(b2.clone(), 100), &tree, &map, 32));
+        assert!(!node.push_vote(Vote::new(b2.clone(), 5), &tree, &map, 32));
+        assert!(node.double_vote_proof(5).is_none());
+        assert!(node.double_votes.is_empty());
+
+        // the times up to the root are forgotten
+        let mut small = LockTower::new(3);
+        for time in 0..10 {
+            assert!(small.push_vote(Vote::new(b1.clone(), time), &tree, &map, 32));
+        }
+        let mut seen: Vec<_> = small.seen_times.iter().cloned().collect();
+        seen.sort();
+        assert_eq!(seen, vec![8, 9]);
+    }
+
+    #[test]
+    fn test_old_vote() {
+        let mut tree = BranchTree::default();
+        let map = ConvergeMap::default();
+        let b1 = Branch { id: 1, base: 0 };
+        tree.insert(b1.clone()).unwrap();
+        let mut node = LockTower::new(32);
+        assert!(node.push_vote(Vote::new(b1.clone(), 5), &tree, &map, 32));
+        let old = Vote::new(b1.clone(), 3);
+        let expected = Err(VoteError::OldVote {
+            last_time: 5,
+            vote_time: 3,
+        });
+        assert_eq!(node.is_safe_to_vote(&old, &tree, &map, 32), expected);
+        assert_eq!(node.try_push_vote(old, &tree, &map, 32), expected);
+        assert_eq!(node.votes.len(), 1);
+        assert!(node.pending_votes.is_empty());
+    }
+
+    #[test]
+    fn test_apply_convergence_update() {
+        let mut tree = BranchTree::default();
+        let map = ConvergeMap::default();
+        let mut network = create_network(100);
+        let mut last = Branch::default();
+        for time in 0..5 {
+            let branch = Branch {
+                id: time + 1,
+                base: last.id,
+            };
+            tree.insert(branch.clone()).unwrap();
+            for node in network.iter_mut() {
+                assert!(node.push_vote(Vote::new(branch.clone(), time), &tree, &map, 32));
+            }
+            last = branch;
+        }
+        let fork = Branch { id: 10, base: 3 };
+        tree.insert(fork.clone()).unwrap();
+        let mut bmap = calc_branch_map(&network, &tree);
+
+        // the node rolls back to the fork
+        let removed = network[42].last_branch();
+        assert!(network[42].push_vote(Vote::new(fork.clone(), 100), &tree, &map, 32));
+        let added = network[42].last_branch();
+        LockTower::apply_convergence_update(&mut bmap, &removed, &added, &tree);
+        assert_eq!(bmap, calc_branch_map(&network, &tree));
+        assert_eq!(bmap[&5], 99);
+        assert_eq!(bmap[&3], 100);
+        assert_eq!(bmap[&10], 1);
+
+        // and back again
+        let removed = added;
+        let branch = Branch { id: 11, base: 5 };
+        tree.insert(branch.clone()).unwrap();
+        assert!(network[42].push_vote(Vote::new(branch.clone(), 200), &tree, &map, 32));
+        LockTower::apply_convergence_update(&mut bmap, &removed, &branch, &tree);
+        assert_eq!(bmap, calc_branch_map(&network, &tree));
+        assert!(!bmap.contains_key(&10));
+    }
+
+    #[test]
+    fn test_fork_switch_cooldown() {
+        let mut tree = BranchTree::default();
+        let map = ConvergeMap::default();
+        let b1 = Branch { id: 1, base: 0 };
+        let b2 = Branch { id: 2, base: 0 };
+        let b3 = Branch { id: 3, base: 1 };
+        tree.insert(b1.clone()).unwrap();
+        tree.insert(b2.clone()).unwrap();
+        tree.insert(b3.clone()).unwrap();
+        let mut node = LockTower::new(32);
+        node.set_fork_switch_cooldown(5);
+        assert!(node.push_vote(Vote::new(b1.clone(), 0), &tree, &map, 32));
+
+        // the first switch is allowed once the vote on b1 expires
+        assert!(node.push_vote(Vote::new(b2.clone(), 3), &tree, &map, 32));
+        assert_eq!(
+            node.is_safe_to_vote(&Vote::new(b3.clone(), 4), &tree, &map, 32),
+            Err(VoteError::CooldownActive { remaining: 4 })
+        );
+        assert_eq!(
+            node.try_push_vote(Vote::new(b3.clone(), 4), &tree, &map, 32),
+            Err(VoteError::CooldownActive { remaining: 4 })
+        );
+        // voting on the same fork is not a switch
+        assert!(node.push_vote(Vote::new(b2.clone(), 5), &tree, &map, 32));
+        assert!(node.push_vote(Vote::new(b3.clone(), 20), &tree, &map, 32));
+        assert_eq!(
+            node.try_push_vote(Vote::new(b2.clone(), 21), &tree, &map, 32),
+            Err(VoteError::CooldownActive { remaining: 4 })
+        );
+        assert_eq!(
+            node.try_push_vote(Vote::new(b2.clone(), 25), &tree, &map, 32),
+            Ok(())
+        );
+
+        // a vote below the finalized branch is rejected for that before the cooldown
+        let mut finalized = tree.clone();
+        finalized.mark_finalized(b3.clone()).unwrap();
+        let vote = Vote::new(b1.clone(), 26);
+        let expected = Err(VoteError::BelowFinalized);
+        assert_eq!(node.is_safe_to_vote(&vote, &finalized, &map, 32), expected);
+        let pushed = node.clone().try_push_vote(vote, &finalized, &map, 32);
+        assert_eq!(pushed, expected);
+        let result = node.apply_votes_from_gossip(&[Vote::new(b3.clone(), 26)], &tree, &map, 32);
+        assert_eq!(result.rejected_cooldown, 1);
+        assert_eq!(result.rejected_branch, 0);
+    }
+
+    #[test]
+    fn test_votes_for_branch() {
+        let mut tree = BranchTree::default();
+        let map = ConvergeMap::default();
+        let b1 = Branch { id: 1, base: 0 };
+        let b2 = Branch { id: 2, base: 1 };
+        let b3 = Branch { id: 3, base: 1 };
+        let b4 = Branch { id: 4, base: 0 };
+        for b in &[&b1, &b2, &b3, &b4] {
+            tree.insert((*b).clone()).unwrap();
+        }
+        let times = |node: &LockTower, b: &Branch| -> Vec<usize> {
+            node.votes_for_branch(b, &tree).map(|v| v.time).collect()
+        };
+        let mut node = LockTower::new(32);
+        for time in 0..3 {
+            assert!(node.push_vote(Vote::new(b1.clone(), time), &tree, &map, 32));
+        }
+        assert_eq!(times(&node, &b1), vec![2, 1, 0]);
+        assert!(times(&node, &b4).is_empty());
+
+        assert!(node.push_vote(Vote::new(b2.clone(), 3), &tree, &map, 32));
+        assert_eq!(times(&node, &b2), vec![3, 2, 1, 0]);
+        assert_eq!(times(&node, &b3), vec![2, 1, 0]);
+        assert_eq!(times(&node, &b1), vec![2, 1, 0]);
+    }
+
+    #[test]
+    fn test_catchup_from_proof() {
+        let mut tree = BranchTree::default();
+        let map = ConvergeMap::default();
+        let b1 = Branch { id: 1, base: 0 };
+        let b2 = Branch { id: 2, base: 1 };
+        let b3 = Branch { id: 3, base: 0 };
+        for b in &[&b1, &b2, &b3] {
+            tree.insert((*b).clone()).unwrap();
+        }
+        let mut node = LockTower::new(4);
+        for (time, b) in [&b1, &b1, &b2, &b2, &b2].iter().enumerate() {
+            assert!(node.push_vote(Vote::new((*b).clone(), time), &tree, &map, 32));
+        }
+        let proof = node.tower_proof();
+        let mut restored = LockTower::new(4);
+        assert_eq!(restored.catchup_from_proof(&proof, &tree), Ok(()));
+        assert_eq!(restored.tower_proof(), proof);
+        assert_eq!(restored.export_votes(), node.export_votes());
+        assert_eq!(restored.branch_trunk.id, 1);
+        let vote = Vote::new(b2.clone(), 5);
+        assert!(node.push_vote(vote.clone(), &tree, &map, 32));
+        assert!(restored.push_vote(vote, &tree, &map, 32));
+        assert_eq!(restored.tower_proof(), node.tower_proof());
+        assert!(!restored.push_vote(Vote::new(b2.clone(), 4), &tree, &map, 32));
+
+        let mut fresh = LockTower::new(4);
+        let mut tampered = proof.clone();
+        tampered.votes[0].1 = 4;
+        assert_eq!(
+            fresh.catchup_from_proof(&tampered, &tree),
+            Err(CatchupError::InvalidSchedule)
+        );
+        let mut tampered = proof.clone();
+        tampered.votes[1].0 = 9;
+        assert_eq!(
+            fresh.catchup_from_proof(&tampered, &tree),
+            Err(CatchupError::BranchNotFound { id: 9 })
+        );
+        let mut tampered = proof.clone();
+        tampered.votes[1].0 = 3;
+        assert_eq!(
+            fresh.catchup_from_proof(&tampered, &tree),
+            Err(CatchupError::InvariantViolation)
+        );
+        let mut tampered = proof.clone();
+        tampered.times[0] = 0;
+        assert_eq!(
+            fresh.catchup_from_proof(&tampered, &tree),
+            Err(CatchupError::InvariantViolation)
+        );
+        assert!(fresh.votes.is_empty());
+        assert_eq!(fresh.branch_trunk.id, 0);
+
+        // the state of the discarded votes is dropped
+        let mut stale = LockTower::new(4);
+        stale.set_fork_switch_cooldown(100);
+        assert!(stale.push_vote(Vote::new(b3.clone(), 6), &tree, &map, 32));
+        assert!(stale.push_vote(Vote::new(b3.clone(), 7), &tree, &map, 32));
+        assert!(!stale.push_vote(Vote::new(b1.clone(), 7), &tree, &map, 32));
+        assert!(!stale.push_vote(Vote::new(b3.clone(), 8), &tree, &map, 1));
+        assert!(stale.double_vote_proof(7).is_some());
+        assert_eq!(stale.votes_not_converged().count(), 1);
+        assert_eq!(stale.catchup_from_proof(&proof, &tree), Ok(()));
+        assert!(stale.double_vote_proof(7).is_none());
+        assert_eq!(stale.votes_not_converged().count(), 0);
+        assert_eq!(stale.finalized_branches().count(), 0);
+        assert!(stale.push_vote(Vote::new(b2.clone(), 7), &tree, &map, 32));
+    }
+
+    #[test]
+    fn test_push_vote_unchecked() {
+        let mut tree = BranchTree::default();
+        let map = ConvergeMap::default();
+        let b1 = Branch { id: 1, base: 0 };
+        let b2 = Branch { id: 2, base: 1 };
+        let b3 = Branch { id: 3, base: 1 };
+        for b in &[&b1, &b2, &b3] {
+            tree.insert((*b).clone()).unwrap();
+        }
+        let votes: Vec<_> = [(&b1, 0), (&b1, 1), (&b2, 2), (&b2, 3), (&b3, 20), (&b3, 21)]
+            .iter()
+            .map(|(b, time)| Vote::new((*b).clone(), *time))
+            .collect();
+        let mut checked = LockTower::new(4);
+        let mut unchecked = LockTower::new(4);
+        for vote in votes {
+            assert!(checked.push_vote(vote.clone(), &tree, &map, 32));
+            unsafe {
+                unchecked.push_vote_unchecked(vote);
+            }
+            assert_eq!(unchecked.export_votes(), checked.export_votes());
+            assert_eq!(unchecked.branch_trunk.id, checked.branch_trunk.id);
+        }
+        assert_eq!(unchecked.branch_trunk.id, 1);
+    }
+
+    #[test]
+    fn test_push_vote_strict() {
+        let tree = BranchTree::default();
+        let map = ConvergeMap::default();
+        let b0 = Branch { id: 0, base: 0 };
+        let mut node = LockTower::new(32);
+        assert_eq!(
+            node.push_vote_strict(Vote::new(b0.clone(), 0), &tree, &map, 32),
+            Ok(())
+        );
+        assert_eq!(
+            node.push_vote_strict(Vote::new(b0.clone(), 0), &tree, &map, 32),
+            Err(VoteError::NonMonotonicTime {
+                expected_min: 1,
+                got: 0
+            })
+        );
+        assert_eq!(node.votes.len(), 1);
+        assert_eq!(
+            node.push_vote_strict(Vote::new(b0.clone(), 1), &tree, &map, 32),
+            Ok(())
+        );
+        assert_eq!(node.votes.len(), 2);
+    }
+
+    #[test]
+    fn test_compute_switching_stake() {
+        let mut tree = BranchTree::default();
+        let map = ConvergeMap::default();
+        let b1 = Branch { id: 1, base: 0 };
+        let b2 = Branch { id: 2, base: 1 };
+        let b3 = Branch { id: 3, base: 2 };
+        let b4 = Branch { id: 4, base: 0 };
+        for b in &[&b1, &b2, &b3, &b4] {
+            tree.insert((*b).clone()).unwrap();
+        }
+        let mut node = LockTower::new(32);
+        assert!(node.push_vote(Vote::new(b1.clone(), 0), &tree, &map, 32));
+        assert!(node.push_vote(Vote::new(b2.clone(), 1), &tree, &map, 32));
+
+        let same = vec![(10, b2.clone()), (20, b3.clone()), (30, b1.clone())];
+        assert_eq!(node.compute_switching_stake(&tree, &same), 0);
+        let other = vec![(10, b4.clone()), (20, b4.clone())];
+        assert_eq!(node.compute_switching_stake(&tree, &other), 30);
+        let mixed = vec![(10, b3.clone()), (20, b4.clone()), (5, Branch::default())];
+        assert_eq!(node.compute_switching_stake(&tree, &mixed), 25);
+    }
+
+    #[test]
+    fn test_apply_bft_rules() {
+        let mut tree = BranchTree::default();
+        let map = ConvergeMap::default();
+        let b1 = Branch { id: 1, base: 0 };
+        let b2 = Branch { id: 2, base: 1 };
+        let b3 = Branch { id: 3, base: 1 };
+        for b in &[&b1, &b2, &b3] {
+            tree.insert((*b).clone()).unwrap();
+        }
+        let mut node = LockTower::new(32);
+        assert!(node.push_vote(Vote::new(b1.clone(), 0), &tree, &map, 32));
+        assert!(node.push_vote(Vote::new(b2.clone(), 1), &tree, &map, 32));
+        // lockouts are 4 on b1 and 2 on b2
+        assert_eq!(
+            node.apply_bft_rules(&Vote::new(b2.clone(), 2), &tree),
+            Ok(())
+        );
+
+        // b3 forks off b1, the vote for b2 is locked out until after time 3
+        assert_eq!(
+            node.apply_bft_rules(&Vote::new(b3.clone(), 2), &tree),
+            Err(BftViolation::ConflictingCommit {
+                committed_branch: 2,
+                committed_lockout: 2
+            })
+        );
+        assert!(!node.push_vote(Vote::new(b3.clone(), 2), &tree, &map, 32));
+
+        // the vote for b2 has expired, b3 doesn't conflict with the vote for b1
+        assert_eq!(
+            node.apply_bft_rules(&Vote::new(b3.clone(), 4), &tree),
+            Err(BftViolation::ExpiresBeforeNewVote)
+        );
+        assert!(node.push_vote(Vote::new(b3.clone(), 4), &tree, &map, 32));
+        assert_eq!(node.last_branch().id, 3);
+    }
+
+    #[test]
+    fn test_compact_encoding() {
+        let mut tree = BranchTree::default();
+        let map = ConvergeMap::default();
+        let b1 = Branch { id: 1, base: 0 };
+        let b2 = Branch { id: 300, base: 1 };
+        tree.insert(b1.clone()).unwrap();
+        tree.insert(b2.clone()).unwrap();
+        let mut node = LockTower::new(32);
+        assert!(node.push_vote(Vote::new(b1, 0), &tree, &map, 32));
+        assert!(node.push_vote(Vote::new(b2.clone(), 1), &tree, &map, 32));
+        assert!(node.push_vote(Vote::new(b2, 2), &tree, &map, 32));
+
+        let bytes = node.to_compact_encoding();
+        let decoded = LockTower::from_compact_encoding(&bytes, 32).unwrap();
+        assert_eq!(decoded.tower_proof(), node.tower_proof());
+        assert_eq!(decoded.export_votes(), node.export_votes());
+        assert_eq!(decoded.last_branch().base, 1);
+
+        let votes: Vec<_> = node
+            .votes
+            .iter()
+            .map(|v| {
+                format!(
+                    "{{\"branch\":{{\"id\":{},\"base\":{}}},\"time\":{},\"lockout\":{}}}",
+                    v.branch.id, v.branch.base, v.time, v.lockout
+                )
+            }).collect();
+        let json = format!(
+            "{{\"branch_trunk\":{{\"id\":0,\"base\":0}},\"votes\":[{}]}}",
+            votes.join(",")
+        );
+        assert!(bytes.len() < json.len());
+
+        assert_eq!(
+            LockTower::from_compact_encoding(&bytes[..bytes.len() - 1], 32).unwrap_err(),
+            DecodeError::Truncated
+        );
+        assert_eq!(
+            LockTower::from_compact_encoding(&[0xff; 11], 32).unwrap_err(),
+            DecodeError::InvalidVarint { offset: 9 }
+        );
+        // votes must get older and their lockouts must grow with depth
+        assert_eq!(
+            LockTower::from_compact_encoding(&[0, 0, 2, 0, 0, 0, 2, 0, 0, 10, 4], 32).unwrap_err(),
+            DecodeError::NotDecreasingTime { index: 1 }
+        );
+        assert_eq!(
+            LockTower::from_compact_encoding(&[0, 0, 2, 0, 0, 10, 2, 0, 0, 0, 3], 32).unwrap_err(),
+            DecodeError::InvalidLockout
+        );
+        assert_eq!(
+            LockTower::from_compact_encoding(&[0, 0, 2, 0, 0, 10, 4, 0, 0, 0, 2], 32).unwrap_err(),
+            DecodeError::InvalidLockout
+        );
+        // a lockout the schedule would overflow before reaching
+        let mut huge = vec![0, 0, 2, 0, 0, 10, 2, 0, 0, 0];
+        huge.extend(&[0xff; 9][..]);
+        huge.push(0x01);
+        assert_eq!(
+            LockTower::from_compact_encoding(&huge, 32).unwrap_err(),
+            DecodeError::InvalidLockout
+        );
+        let decoded = LockTower::from_compact_encoding(&[0, 0, 2, 0, 0, 10, 2, 0, 0, 0, 16], 32);
+        assert!(decoded.unwrap().verify_invariants(&tree));
+        assert_eq!(
+            LockTower::from_compact_encoding(&bytes, 3).unwrap_err(),
+            DecodeError::TooManyVotes {
+                len: 3,
+                max_size: 3
+            }
+        );
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert_eq!(
+            LockTower::from_compact_encoding(&trailing, 32).unwrap_err(),
+            DecodeError::TrailingBytes {
+                offset: bytes.len()
+            }
+        );
+    }
+
+    #[test]
+    fn test_quorum_root() {
+        let mut tree = BranchTree::default();
+        let map = ConvergeMap::default();
+        let b1 = Branch { id: 1, base: 0 };
+        let b2 = Branch { id: 2, base: 1 };
+        let b3 = Branch { id: 3, base: 1 };
+        for b in &[&b1, &b2, &b3] {
+            tree.insert((*b).clone()).unwrap();
+        }
+        let two_thirds = 2.0 / 3.0;
+        assert_eq!(LockTower::quorum_root(&[], &tree, 0, two_thirds), None);
+
+        let mut network = create_network(3);
+        let votes = [b1.clone(), b1.clone(), b1.clone(), b2.clone()];
+        for node in network.iter_mut() {
+            for (time, b) in votes.iter().enumerate() {
+                assert!(node.push_vote(Vote::new(b.clone(), time), &tree, &map, 32));
+            }
+        }
+        let root = |network: &[LockTower], time, fraction| {
+            LockTower::quorum_root(network, &tree, time, fraction)
+        };
+        // lockouts are 16, 8, 4, 2, everything is locked out at time 3
+        assert_eq!(root(&network, 3, two_thirds), Some(3));
+        // only the oldest votes are still locked out
+        assert_eq!(root(&network, 9, two_thirds), Some(0));
+        assert_eq!(root(&network, 16, two_thirds), None);
+
+        // one node switches to b3 once its vote for b2 expires
+        assert!(network[0].push_vote(Vote::new(b3.clone(), 6), &tree, &map, 32));
+        assert_eq!(root(&network, 6, two_thirds), Some(1));
+        assert_eq!(root(&network, 6, 0.0), Some(6));
+    }
+
+    #[test]
+    fn test_max_tolerable_faults() {
+        assert_eq!(LockTower::max_tolerable_faults(100, 67), 33);
+        assert_eq!(LockTower::max_tolerable_faults(10, 67), 3);
+        assert_eq!(LockTower::max_tolerable_faults(100, 100), 0);
+        assert_eq!(LockTower::max_tolerable_faults(0, 67), 0);
+
+        let tree = BranchTree::default();
+        let b0 = Branch { id: 0, base: 0 };
+        let mut node = LockTower::new(32);
+        let mut map = ConvergeMap::default();
+        assert_eq!(node.current_safety_margin(&map, 100), 0.0);
+        assert!(node.push_vote(Vote::new(b0, 0), &tree, &map, 32));
+        map.set(0, 80);
+        assert!((node.current_safety_margin(&map, 100) - 0.3).abs() < 1e-9);
+        map.set(0, 40);
+        assert_eq!(node.current_safety_margin(&map, 100), 0.0);
+    }
+
+    #[test]
+    fn test_dump_state() {
+        let mut tree = BranchTree::default();
+        let map = ConvergeMap::default();
+        let b1 = Branch { id: 1, base: 0 };
+        let b2 = Branch { id: 2, base: 1 };
+        tree.insert(b1.clone()).unwrap();
+        tree.insert(b2.clone()).unwrap();
+        let mut node = LockTower::new(32);
+        assert!(node.push_vote(Vote::new(b1.clone(), 0), &tree, &map, 32));
+        assert!(node.push_vote(Vote::new(b2.clone(), 1), &tree, &map, 32));
+        let dump = node.dump_state(3, &tree);
+        assert_eq!(
+            dump.to_string(),
+            "time 3 trunk 0 votes 2\n\
+             \x20 time branch depth   age lockout remaining  status\n\
+             \x20    1      2     2     2       2         0 expired\n\
+             \x20    0      1     1     3       4         1  active\n"
+        );
+        assert_eq!(
+            dump.to_json(),
+            "{\"current_time\":3,\"branch_trunk\":0,\"votes\":[\
+             {\"time\":1,\"branch\":2,\"depth\":2,\"age\":2,\"lockout\":2,\"remaining\":0,\"expired\":true},\
+             {\"time\":0,\"branch\":1,\"depth\":1,\"age\":3,\"lockout\":4,\"remaining\":1,\"expired\":false}]}"
+        );
+
+        // the vote for b2 is rolled back
+        assert!(node.push_vote(Vote::new(b1.clone(), 4), &tree, &map, 32));
+        assert_eq!(
+            node.dump_state(4, &tree).to_string(),
+            "time 4 trunk 0 votes 2\n\
+             \x20 time branch depth   age lockout remaining  status\n\
+             \x20    4      1     1     0       2         2  active\n\
+             \x20    0      1     1     4       4         0 expired\n"
+        );
+        // the grace slots keep the old vote active
+        node.set_grace_slots(1);
+        let dump = node.dump_state(4, &tree);
+        assert_eq!(dump.rows[1].remaining, 1);
+        assert!(!dump.rows[1].expired);
+    }
+
+    #[test]
+    fn test_merge_towers() {
+        let mut tree = BranchTree::default();
+        let map = ConvergeMap::default();
+        let b1 = Branch { id: 1, base: 0 };
+        let b2 = Branch { id: 2, base: 1 };
+        let b3 = Branch { id: 3, base: 1 };
+        for b in &[&b1, &b2, &b3] {
+            tree.insert((*b).clone()).unwrap();
+        }
+        let empty = LockTower::merge_towers(&[], &tree);
+        assert!(empty.tip.is_none());
+        assert!(empty.votes.is_empty());
+        assert!(empty.slot_counts.is_empty());
+        assert_eq!(empty.confidence(), 0.0);
+
+        let mut a = LockTower::new(32);
+        assert!(a.push_vote(Vote::new(b1.clone(), 0), &tree, &map, 32));
+        assert!(a.push_vote(Vote::new(b2.clone(), 1), &tree, &map, 32));
+        let same = LockTower::merge_towers(&[&a, &a.clone(), &a.clone()], &tree);
+        assert_eq!(same.tip.as_ref().map(|b| b.id), Some(2));
+        assert_eq!(same.confidence(), 1.0);
+        assert_eq!(same.votes.len(), 2);
+        assert_eq!(same.votes[0].time, 1);
+        assert_eq!(same.slot_counts[&1][&2], 3);
+
+        let mut b = LockTower::new(32);
+        assert!(b.push_vote(Vote::new(b1.clone(), 0), &tree, &map, 32));
+        assert!(b.push_vote(Vote::new(b3.clone(), 1), &tree, &map, 32));
+        let split = LockTower::merge_towers(&[&a, &b, &b.clone()], &tree);
+        assert_eq!(split.tip.as_ref().map(|b| b.id), Some(3));
+        assert_eq!(split.tip_count, 2);
+        assert_eq!(split.votes.len(), 3);
+        assert_eq!(split.slot_counts[&0][&1], 3);
+        assert_eq!(split.slot_counts[&1][&2], 1);
+        assert_eq!(split.slot_counts[&1][&3], 2);
+    }
+
+    #[test]
+    fn test_apply_vote_message() {
+        /// the signature is the voter id repeated
+        struct VoterIdVerifier;
+        impl SignatureVerifier for VoterIdVerifier {
+            fn verify(&self, msg: &[u8], sig: &[u8; 64], pubkey: u64) -> bool {
+                msg[..8] == pubkey.to_le_bytes() && sig.iter().all(|b| *b == pubkey as u8)
+            }
+        }
+        let tree = BranchTree::default();
+        let map = ConvergeMap::default();
+        let b0 = Branch { id: 0, base: 0 };
+        let mut node = LockTower::new(32);
+        let mut msg = VoteMessage {
+            voter_id: 7,
+            vote: Vote::new(b0.clone(), 0),
+            signature: [0; 64],
+        };
+        assert_eq!(
+            node.apply_vote_message(&msg, &NoopVerifier, &tree, &map, 32),
+            Ok(())
+        );
+        msg.vote = Vote::new(b0.clone(), 1);
+        assert_eq!(
+            node.apply_vote_message(&msg, &VoterIdVerifier, &tree, &map, 32),
+            Err(VoteError::InvalidSignature)
+        );
+        assert_eq!(node.votes.len(), 1);
+        msg.signature = [7; 64];
+        assert_eq!(
+            node.apply_vote_message(&msg, &VoterIdVerifier, &tree, &map, 32),
+            Ok(())
+        );
+        assert_eq!(
+            node.apply_vote_message(&msg, &VoterIdVerifier, &tree, &map, 32),
+            Err(VoteError::DoubleVote { time: 1 })
+        );
+        assert_eq!(node.votes.len(), 2);
+    }
+
+    #[test]
+    fn test_push_vote_with_fallback() {
+        let mut tree = BranchTree::default();
+        let b1 = Branch { id: 1, base: 0 };
+        tree.insert(b1.clone()).unwrap();
+        let mut map = ConvergeMap::default();
+        map.set(1, 40);
+        let mut node = LockTower::new(32);
+        assert!(node.push_vote(Vote::new(b1.clone(), 0), &tree, &map, 32));
+        assert!(node.push_vote(Vote::new(b1.clone(), 1), &tree, &map, 32));
+        // the vote for b1 at depth 1 only has 40% of the network
+        let push = |node: &mut LockTower, time, fallback, stalled| {
+            let vote = Vote::new(b1.clone(), time);
+            node.push_vote_with_fallback(vote, &tree, &map, 1, &fallback, stalled)
+        };
+        let never = ConvergenceFallback::NeverFallback;
+        assert_eq!(push(&mut node, 2, never, 100), Err(VoteError::NotConverged));
+
+        let after = ConvergenceFallback::FallbackAfterRounds(3);
+        for stalled in 0..3 {
+            let mut node = node.clone();
+            let result = push(&mut node, 2, after, stalled);
+            assert_eq!(result, Err(VoteError::NotConverged));
+        }
+        let mut fallen_back = node.clone();
+        assert_eq!(push(&mut fallen_back, 2, after, 3), Ok(()));
+        assert_eq!(fallen_back.votes.len(), 3);
+
+        let lower = ConvergenceFallback::TemporaryThresholdLower {
+            rounds: 2,
+            lower_pct: 30,
+        };
+        let results: Vec<_> = (0..4)
+            .map(|stalled| push(&mut node.clone(), 2, lower, stalled).is_ok())
+            .collect();
+        assert_eq!(results, vec![false, true, true, false]);
+        let too_low = ConvergenceFallback::TemporaryThresholdLower {
+            rounds: 2,
+            lower_pct: 40,
+        };
+        assert_eq!(push(&mut node, 2, too_low, 1), Err(VoteError::NotConverged));
+    }
+
+    #[test]
+    fn test_push_vote_timed() {
+        let tree = BranchTree::default();
+        let map = ConvergeMap::default();
+        let b0 = Branch { id: 0, base: 0 };
+        let mut node = LockTower::new(32);
+        let (result, timings) = node.push_vote_timed(Vote::new(b0.clone(), 0), &tree, &map, 32);
+        assert_eq!(result, Ok(()));
+        assert_eq!(timings, VoteTimings::default());
+
+        node.set_timing_enabled(true);
+        for time in 1..8 {
+            let (result, t) = node.push_vote_timed(Vote::new(b0.clone(), time), &tree, &map, 32);
+            assert_eq!(result, Ok(()));
+            let phases = t.rollback + t.validity + t.convergence + t.enter_vote;
+            assert!(phases <= t.total);
+            assert!(t.total > Duration::default());
+        }
+        let (result, t) = node.push_vote_timed(Vote::new(b0.clone(), 7), &tree, &map, 32);
+        assert_eq!(result, Err(VoteError::DoubleVote { time: 7 }));
+        assert_eq!(t.enter_vote, Duration::default());
+        assert_eq!(node.votes.len(), 8);
+    }
+
+    #[test]
+    fn test_push_vote_validated() {
+        let tree = BranchTree::default();
+        let map = ConvergeMap::default();
+        let b0 = Branch { id: 0, base: 0 };
+        let oracle = MockSlotOracle(10);
+        let mut node = LockTower::new(32);
+        assert_eq!(
+            node.push_vote_validated(Vote::new(b0.clone(), 14), &tree, &map, 32, &oracle, 3),
+            Err(VoteError::FutureVote {
+                slot: 14,
+                current: 10
+            })
+        );
+        assert!(node.votes.is_empty());
+        assert_eq!(
+            node.push_vote_validated(Vote::new(b0.clone(), 13), &tree, &map, 32, &oracle, 3),
+            Ok(())
+        );
+        assert_eq!(
+            node.push_vote_validated(Vote::new(b0.clone(), 12), &tree, &map, 32, &oracle, 3),
+            Err(VoteError::NonMonotonicTime {
+                expected_min: 14,
+                got: 12
+            })
+        );
+        assert_eq!(node.votes.len(), 1);
+    }
+
+    #[test]
+    fn test_time_weighted_commitment() {
+        let mut tree = BranchTree::default();
+        let map = ConvergeMap::default();
+        let b1 = Branch { id: 1, base: 0 };
+        tree.insert(b1.clone()).unwrap();
+        let mut node = LockTower::new(32);
+        assert_eq!(node.time_weighted_commitment(5), 0.0);
+        assert!(node.push_vote(Vote::new(b1.clone(), 0), &tree, &map, 32));
+        assert_eq!(node.time_weighted_commitment(0), 0.0);
+
+        let mut equal = node.clone();
+        assert!(equal.push_vote(Vote::new(b1.clone(), 2), &tree, &map, 32));
+        for vote in equal.votes_iter_mut() {
+            vote.lockout = 2;
+        }
+        // ages 2 and 4
+        assert_eq!(equal.time_weighted_commitment(4), 3.0);
+        // lockouts 4 and 2, pulled past the mean age toward the older vote
+        equal.repair_lockouts();
+        assert!(equal.time_weighted_commitment(4) > 3.0);
 
-impl LockTower {
-    pub fn new(max_size: usize) -> Self {
-        Self {
-            votes: VecDeque::new(),
-            max_size,
-            branch_trunk: Branch::default(),
+        let mut last = node.time_weighted_commitment(0);
+        for time in 1..8 {
+            assert!(node.push_vote(Vote::new(b1.clone(), time), &tree, &map, 32));
+            let commitment = node.time_weighted_commitment(time);
+            assert!(commitment > last);
+            last = commitment;
         }
     }
-    pub fn push_vote(
-        &mut self,
-        vote: Vote,
-        branch_tree: &HashMap<usize, Branch>,
-        converge_map: &HashMap<usize, usize>,
-        depth: usize,
-    ) -> bool {
-        self.rollback(vote.time);
-        if !self.is_valid(&vote, branch_tree) {
-            return false;
-        }
-        if !self.is_converged(converge_map, depth) {
-            return false;
+
+    #[test]
+    fn test_weighted_tower_strength() {
+        let tree = BranchTree::default();
+        let map = ConvergeMap::default();
+        let b0 = Branch { id: 0, base: 0 };
+        let mut node = LockTower::new(32);
+        assert_eq!(node.tower_weight(), 0);
+        for time in 0..3 {
+            assert!(node.push_vote(Vote::new(b0.clone(), time), &tree, &map, 32));
         }
-        self.enter_vote(vote);
-        if self.is_full() {
-            self.pop_full();
+        assert_eq!(node.tower_weight(), 2 + 4 + 8);
+        assert_eq!(node.weighted_tower_strength(0, 100), 0.0);
+        assert_eq!(node.weighted_tower_strength(50, 100), 7.0);
+        assert_eq!(node.weighted_tower_strength(100, 100), 14.0);
+        assert_eq!(node.weighted_tower_strength(10, 0), 0.0);
+        for stake in 1..10 {
+            let strength = node.weighted_tower_strength(stake * 10, 100);
+            assert!((strength - stake as f64 * 1.4).abs() < 1e-9);
         }
-        true
-    }
-    /// check if the vote at `depth` has over 50% of the network committed
-    fn is_converged(&self, converge_map: &HashMap<usize, usize>, depth: usize) -> bool {
-        self.get_vote(depth)
-            .map(|v| {
-                let v = *converge_map.get(&v.branch.id).unwrap_or(&0);
-                // hard coded to 100 nodes
-                assert!(v <= 100);
-                v > 50
-            }).unwrap_or(true)
+
+        let mut other = LockTower::new(32);
+        assert!(other.push_vote(Vote::new(b0.clone(), 0), &tree, &map, 32));
+        let towers = vec![(node.clone(), 25), (other.clone(), 75)];
+        assert_eq!(LockTower::network_tower_strength(&towers, 100), 3.5 + 1.5);
+        let towers = vec![(node, 50), (other, 150)];
+        assert_eq!(LockTower::network_tower_strength(&towers, 100), 10.0);
     }
 
-    /// if a vote is expired, pop it and all the votes leading up to it
-    fn rollback(&mut self, time: usize) {
-        let mut last: isize = -1;
-        for (i, v) in self.votes.iter().enumerate() {
-            if v.lock_height() < time {
-                last = i as isize;
-            }
+    #[test]
+    fn test_retry_pending() {
+        let mut tree = BranchTree::default();
+        let mut map = ConvergeMap::default();
+        let b1 = Branch { id: 1, base: 0 };
+        let b2 = Branch { id: 2, base: 1 };
+        tree.insert(b1.clone()).unwrap();
+        tree.insert(b2.clone()).unwrap();
+        let mut node = LockTower::new(32);
+        assert!(node.push_vote(Vote::new(b1.clone(), 0), &tree, &map, 1));
+        assert!(node.push_vote(Vote::new(b1.clone(), 1), &tree, &map, 1));
+        assert_eq!(
+            node.try_push_vote(Vote::new(b2.clone(), 2), &tree, &map, 1),
+            Err(VoteError::NotConverged)
+        );
+        assert!(!node.push_vote(Vote::new(b2.clone(), 3), &tree, &map, 1));
+        let pending: Vec<_> = node.votes_not_converged().map(|v| v.time).collect();
+        assert_eq!(pending, vec![2, 3]);
+        assert_eq!(node.retry_pending(&tree, &map, 1), 0);
+        assert_eq!(node.votes_not_converged().count(), 2);
+
+        map.set(1, 51);
+        assert_eq!(node.retry_pending(&tree, &map, 1), 2);
+        assert_eq!(node.votes_not_converged().count(), 0);
+        assert_eq!(node.last_vote().unwrap().time, 3);
+        assert_eq!(node.last_branch().id, 2);
+
+        // retried votes are kept once per time, votes that are no longer newer than the
+        // last vote are dropped, and the queue is capped
+        let b3 = Branch { id: 3, base: 2 };
+        tree.insert(b3.clone()).unwrap();
+        map.set(2, 51);
+        assert!(node.push_vote(Vote::new(b2.clone(), 4), &tree, &map, 1));
+        map.set(2, 0);
+        for _ in 0..2 {
+            assert!(!node.push_vote(Vote::new(b3.clone(), 6), &tree, &map, 1));
         }
-        for _ in 0..(last + 1) {
-            self.votes.pop_front();
+        let pending: Vec<_> = node.votes_not_converged().map(|v| v.time).collect();
+        assert_eq!(pending, vec![6]);
+        map.set(2, 51);
+        assert!(node.push_vote(Vote::new(b2.clone(), 7), &tree, &map, 1));
+        map.set(2, 0);
+        assert_eq!(node.retry_pending(&tree, &map, 1), 0);
+        assert_eq!(node.votes_not_converged().count(), 0);
+        for time in 8..8 + 2 * MAX_PENDING_VOTES {
+            node.queue_pending(Vote::new(b3.clone(), time));
         }
+        let pending: Vec<_> = node.votes_not_converged().map(|v| v.time).collect();
+        assert_eq!(pending.len(), MAX_PENDING_VOTES);
+        assert_eq!(pending[0], 8 + MAX_PENDING_VOTES);
     }
-    /// only add votes that are descendent from the last vote in the stack
-    fn is_valid(&mut self, vote: &Vote, branch_tree: &HashMap<usize, Branch>) -> bool {
-        self.last_branch().is_trunk_of(&vote.branch, branch_tree)
+
+    #[test]
+    fn test_min_slots_to_finalize() {
+        assert_eq!(LockTower::min_slots_to_finalize(0, 2, 2), 0);
+        assert_eq!(LockTower::min_slots_to_finalize(1, 2, 2), 2);
+        assert_eq!(LockTower::min_slots_to_finalize(4, 2, 2), 30);
+        assert_eq!(LockTower::min_slots_to_finalize(32, 2, 2), (1 << 33) - 2);
+        assert_eq!(LockTower::min_slots_to_finalize(3, 1, 3), 1 + 3 + 9);
+        assert_eq!(LockTower::min_slots_to_finalize(128, 2, 2), usize::MAX);
     }
-    fn enter_vote(&mut self, vote: Vote) {
-        let vote_time = vote.time;
-        assert!(!self.is_full());
-        assert_eq!(vote.lockout, 2);
-        // push the new vote to the font
-        self.votes.push_front(vote);
-        // double the lockouts if the threshold to doulbe is met
-        for i in 1..self.votes.len() {
-            assert!(self.votes[i].time <= vote_time);
-            if self.votes[i].lockout == self.votes[i - 1].lockout {
-                self.votes[i].lockout *= 2;
+
+    #[test]
+    fn test_network_health_score() {
+        let mut tree = BranchTree::default();
+        let map = ConvergeMap::default();
+        let branches: Vec<_> = (1..5).map(|id| Branch { id, base: id - 1 }).collect();
+        for b in &branches {
+            tree.insert(b.clone()).unwrap();
+        }
+        assert_eq!(LockTower::network_health_score(&[], &tree, 4), 0.0);
+
+        let mut network: Vec<_> = (0..4).map(|_| LockTower::new(5)).collect();
+        // no votes yet, every node is trivially converged on the root
+        assert_eq!(LockTower::network_health_score(&network, &tree, 4), 0.5);
+
+        // every node is on its own fork off of branch 1
+        for (i, node) in network.iter_mut().enumerate() {
+            let fork = Branch { id: 5 + i, base: 1 };
+            tree.insert(fork.clone()).unwrap();
+            assert!(node.push_vote(Vote::new(branches[0].clone(), 0), &tree, &map, 32));
+            assert!(node.push_vote(Vote::new(fork, 1), &tree, &map, 32));
+        }
+        let mut scores = vec![];
+        for converged in 0..network.len() {
+            let mut network = network.clone();
+            for node in network.iter_mut().take(converged + 1) {
+                node.reset();
+                for (time, b) in branches.iter().enumerate() {
+                    assert!(node.push_vote(Vote::new(b.clone(), time), &tree, &map, 32));
+                }
             }
+            scores.push(LockTower::network_health_score(&network, &tree, 4));
         }
+        let increasing = scores.windows(2).all(|w| w[0] < w[1]);
+        assert!(increasing);
+        assert_eq!(scores[3], 1.0);
     }
-    fn pop_full(&mut self) {
-        assert!(self.is_full());
-        self.branch_trunk = self.votes.pop_back().unwrap().branch;
+
+    #[test]
+    fn test_vote_sequence() {
+        let tree = BranchTree::default();
+        let map = ConvergeMap::default();
+        let b0 = Branch { id: 0, base: 0 };
+        let mut node = LockTower::new(32);
+        assert_eq!(node.vote_sequence(), 0);
+        let mut last = 0;
+        for time in &[0, 1, 2, 20, 21] {
+            assert!(node.push_vote(Vote::new(b0.clone(), *time), &tree, &map, 32));
+            assert!(node.vote_sequence() > last);
+            last = node.vote_sequence();
+        }
+        // votes 1 and 2 were rolled back by the vote at 20
+        assert!(node.vote_at_time(2).is_none());
+        assert_eq!(node.vote_sequence(), 5);
+        assert_eq!(node.sequence_at_time(0), Some(1));
+        assert_eq!(node.sequence_at_time(2), Some(3));
+        assert_eq!(node.sequence_at_time(21), Some(5));
+        assert_eq!(node.sequence_at_time(3), None);
+        node.reset();
+        assert_eq!(node.vote_sequence(), 5);
+        assert_eq!(node.sequence_at_time(0), None);
+
+        // the sequences of rooted and pruned times are dropped
+        let mut node = LockTower::new(3);
+        for time in 0..4 {
+            assert!(node.push_vote(Vote::new(b0.clone(), time), &tree, &map, 32));
+        }
+        assert_eq!(node.last_rooted_slot(), Some(1));
+        assert_eq!(node.sequence_at_time(1), None);
+        assert_eq!(node.sequence_at_time(2), Some(3));
+        assert_eq!(node.sequence_at_time.len(), 2);
+        node.prune_votes_before(3);
+        assert_eq!(node.sequence_at_time(2), None);
+        assert_eq!(node.sequence_at_time(3), Some(4));
     }
-    fn is_full(&self) -> bool {
-        assert!(self.votes.len() <= self.max_size);
-        self.votes.len() == self.max_size
+
+    #[test]
+    fn test_expected_root_slot() {
+        let tree = BranchTree::default();
+        let map = ConvergeMap::default();
+        let b0 = Branch { id: 0, base: 0 };
+        let mut node = LockTower::new(4);
+        assert_eq!(node.expected_root_slot(), None);
+        assert!(node.push_vote(Vote::new(b0.clone(), 0), &tree, &map, 32));
+        assert_eq!(node.expected_root_slot(), Some(3));
+        assert!(node.push_vote(Vote::new(b0.clone(), 1), &tree, &map, 32));
+        assert!(node.push_vote(Vote::new(b0.clone(), 2), &tree, &map, 32));
+        assert_eq!(node.expected_root_slot(), Some(3));
+        assert!(node.push_vote(Vote::new(b0.clone(), 3), &tree, &map, 32));
+        assert_eq!(node.commit_history()[0].0, 0);
+
+        // a full tower is rooted right away, force one to check the edge case
+        node.votes.push_back(Vote::new(b0.clone(), 0));
+        assert!(node.is_full());
+        assert_eq!(node.expected_root_slot(), None);
     }
-    fn last_vote(&self) -> Option<&Vote> {
-        self.votes.front()
+
+    #[test]
+    fn test_last_rooted_slot() {
+        let mut tree = BranchTree::default();
+        let map = ConvergeMap::default();
+        let branches: Vec<_> = (1..6).map(|id| Branch { id, base: id - 1 }).collect();
+        for b in &branches {
+            tree.insert(b.clone()).unwrap();
+        }
+        let mut node = LockTower::new(3);
+        assert_eq!(node.last_rooted_slot(), None);
+        assert!(node.last_rooted_branch().is_none());
+        for (time, b) in branches.iter().take(3).enumerate() {
+            assert!(node.push_vote(Vote::new(b.clone(), time), &tree, &map, 32));
+        }
+        assert_eq!(node.last_rooted_slot(), Some(0));
+        assert_eq!(node.last_rooted_branch().map(|b| b.id), Some(1));
+        for (time, b) in branches.iter().enumerate().skip(3) {
+            assert!(node.push_vote(Vote::new(b.clone(), time), &tree, &map, 32));
+        }
+        assert_eq!(node.last_rooted_slot(), Some(2));
+        assert_eq!(node.last_rooted_branch().map(|b| b.id), Some(3));
+        // moving the trunk without rooting a vote keeps the last rooted vote
+        assert_eq!(node.rebase(branches[3].clone(), &tree), Ok(()));
+        assert_eq!(node.last_rooted_slot(), Some(2));
+        assert_eq!(node.last_rooted_branch().map(|b| b.id), Some(3));
+        node.reset();
+        assert_eq!(node.last_rooted_slot(), None);
+        assert!(node.last_rooted_branch().is_none());
     }
-    fn get_vote(&self, ix: usize) -> Option<&Vote> {
-        self.votes.get(ix)
+
+    #[test]
+    fn test_is_expired_tower() {
+        let mut tree = BranchTree::default();
+        let map = ConvergeMap::default();
+        let b1 = Branch { id: 1, base: 0 };
+        tree.insert(b1.clone()).unwrap();
+        let mut node = LockTower::new(32);
+        assert!(!node.is_expired_tower(100));
+        assert!(node.push_vote(Vote::new(b1.clone(), 0), &tree, &map, 32));
+        assert!(node.push_vote(Vote::new(b1.clone(), 1), &tree, &map, 32));
+        // lock heights are 4 and 3
+        assert!(!node.is_expired_tower(4));
+        assert!(node.is_expired_tower(5));
+        assert!(!node.is_stale(5, 10));
+        node.pop_expired_votes(5);
+        assert!(node.is_expired_tower(5));
+
+        let mut rooted = LockTower::new(2);
+        assert!(rooted.push_vote(Vote::new(b1.clone(), 0), &tree, &map, 32));
+        assert!(rooted.push_vote(Vote::new(b1.clone(), 1), &tree, &map, 32));
+        assert_eq!(rooted.branch_trunk.id, 1);
+        assert!(!rooted.is_expired_tower(100));
+        node.reset();
+        assert!(!node.is_expired_tower(100));
     }
-    pub fn first_vote(&self) -> Option<&Vote> {
-        self.votes.back()
+
+    #[test]
+    fn test_vote_staleness_map() {
+        let mut tree = BranchTree::default();
+        let map = ConvergeMap::default();
+        let b1 = Branch { id: 1, base: 0 };
+        tree.insert(b1.clone()).unwrap();
+        assert!(LockTower::vote_staleness_map(&[], 10, 5).is_empty());
+        let mut network: Vec<(u64, LockTower)> = (0..3)
+            .map(|id| {
+                let mut t = LockTower::new(32);
+                assert!(t.push_vote(Vote::new(b1.clone(), 8), &tree, &map, 32));
+                (id, t)
+            })
+            .collect();
+        assert!(LockTower::vote_staleness_map(&network, 10, 5).is_empty());
+        let mut behind = LockTower::new(32);
+        assert!(behind.push_vote(Vote::new(b1.clone(), 2), &tree, &map, 32));
+        network[1].1 = behind;
+        let stale = LockTower::vote_staleness_map(&network, 10, 5);
+        assert_eq!(stale.len(), 1);
+        assert_eq!(stale[&1], 8);
     }
-    pub fn last_branch(&self) -> Branch {
-        self.last_vote()
-            .map(|v| v.branch.clone())
-            .unwrap_or(self.branch_trunk.clone())
+
+    #[test]
+    fn test_finality_lag() {
+        let tree = BranchTree::default();
+        let map = ConvergeMap::default();
+        let b0 = Branch { id: 0, base: 0 };
+        let mut node = LockTower::new(3);
+        assert_eq!(node.finality_lag(10), None);
+        assert!(!node.is_lagging(10, 5));
+        for time in 0..3 {
+            assert!(node.push_vote(Vote::new(b0.clone(), time), &tree, &map, 32));
+        }
+        assert_eq!(node.commit_history().len(), 1);
+        assert_eq!(node.finality_lag(0), Some(0));
+        assert_eq!(node.finality_lag(10), Some(10));
+        assert!(!node.is_lagging(5, 5));
+        assert!(node.is_lagging(6, 5));
     }
-}
 
-#[cfg(test)]
-mod test {
-    use super::*;
-    use rand::{thread_rng, Rng};
+    #[test]
+    fn test_total_votes_cast() {
+        let tree = BranchTree::default();
+        let map = ConvergeMap::default();
+        let b0 = Branch { id: 0, base: 0 };
+        let mut node = LockTower::new(4);
+        let active = |node: &LockTower| {
+            node.total_votes_cast() - node.total_votes_rolled_back() - node.commit_history().len()
+        };
+        for time in &[0, 1, 2, 10, 11, 12, 13, 14, 40, 41] {
+            assert!(node.push_vote(Vote::new(b0.clone(), *time), &tree, &map, 32));
+            assert_eq!(active(&node), node.votes.len());
+        }
+        assert_eq!(node.total_votes_cast(), 10);
+        assert!(node.total_votes_rolled_back() > 0);
+        assert!(!node.commit_history().is_empty());
+        node.pop_expired_votes(100);
+        assert!(node.votes.is_empty());
+        assert_eq!(active(&node), 0);
+        // a reset drops the votes without counting them, and keeps the totals
+        assert!(node.push_vote(Vote::new(b0.clone(), 100), &tree, &map, 32));
+        node.reset();
+        assert_eq!(node.total_votes_cast(), 11);
+        assert_eq!(node.votes.len(), 0);
+    }
 
     #[test]
-    fn test_is_trunk_of_1() {
-        let tree = HashMap::new();
-        let b1 = Branch { id: 1, base: 0 };
-        let b2 = Branch { id: 2, base: 0 };
-        assert!(!b1.is_trunk_of(&b2, &tree));
+    fn test_commit_history() {
+        let mut tree = BranchTree::default();
+        let map = ConvergeMap::default();
+        let branches: Vec<_> = (1..7).map(|id| Branch { id, base: id - 1 }).collect();
+        for b in &branches {
+            tree.insert(b.clone()).unwrap();
+        }
+        let mut node = LockTower::new(4);
+        assert!(node.commit_history().is_empty());
+        for (time, b) in branches.iter().enumerate() {
+            assert!(node.push_vote(Vote::new(b.clone(), time), &tree, &map, 32));
+            if let Some((_, last)) = node.commit_history().last() {
+                assert_eq!(last.id, node.branch_trunk.id);
+            }
+        }
+        let history: Vec<_> = node
+            .commit_history()
+            .iter()
+            .map(|(time, b)| (*time, b.id))
+            .collect();
+        assert_eq!(history, vec![(0, 1), (1, 2), (2, 3)]);
+
+        let mut preserved = node.clone();
+        preserved.set_preserve_history(true);
+        preserved.reset();
+        assert_eq!(preserved.commit_history().len(), 3);
+        node.reset();
+        assert!(node.commit_history().is_empty());
     }
+
     #[test]
-    fn test_is_trunk_of_2() {
-        let tree = HashMap::new();
+    fn test_apply_votes_ordered() {
+        let mut tree = BranchTree::default();
+        let map = ConvergeMap::default();
         let b1 = Branch { id: 1, base: 0 };
-        let b2 = Branch { id: 0, base: 0 };
-        assert!(!b1.is_trunk_of(&b2, &tree));
+        let b2 = Branch { id: 2, base: 1 };
+        let b3 = Branch { id: 3, base: 2 };
+        let b4 = Branch { id: 4, base: 2 };
+        for b in &[&b1, &b2, &b3, &b4] {
+            tree.insert((*b).clone()).unwrap();
+        }
+        let votes = vec![
+            Vote::new(b4.clone(), 2),
+            Vote::new(b2.clone(), 1),
+            Vote::new(b3.clone(), 2),
+            Vote::new(b1.clone(), 0),
+        ];
+        let mut node = LockTower::new(32);
+        let results = node.apply_votes_ordered(votes.clone(), &tree, &map, 32);
+        let double = Err(VoteError::DoubleVote { time: 2 });
+        assert_eq!(results, vec![double, Ok(()), Ok(()), Ok(())]);
+
+        let mut sorted = votes;
+        sorted.sort_by_key(|v| (v.time, v.branch.id));
+        let mut expected = LockTower::new(32);
+        for vote in sorted {
+            let _ = expected.try_push_vote(vote, &tree, &map, 32);
+        }
+        let state = |t: &LockTower| -> Vec<_> {
+            t.votes
+                .iter()
+                .map(|v| (v.branch.id, v.time, v.lockout))
+                .collect()
+        };
+        assert_eq!(state(&node), state(&expected));
+        assert_eq!(node.last_branch().id, 3);
     }
+
     #[test]
-    fn test_is_trunk_of_3() {
-        let tree = HashMap::new();
+    fn test_catchup() {
+        let mut tree = BranchTree::default();
+        let map = ConvergeMap::default();
         let b1 = Branch { id: 1, base: 0 };
-        let b2 = Branch { id: 1, base: 0 };
-        assert!(b1.is_trunk_of(&b2, &tree));
+        let b2 = Branch { id: 2, base: 0 };
+        let b3 = Branch { id: 3, base: 2 };
+        let b4 = Branch { id: 4, base: 1 };
+        for b in &[&b1, &b2, &b3, &b4] {
+            tree.insert((*b).clone()).unwrap();
+        }
+        let mut stale = LockTower::new(8);
+        for time in 0..4 {
+            assert!(stale.push_vote(Vote::new(b1.clone(), time), &tree, &map, 32));
+        }
+        let mut native = stale.clone();
+        let recent: Vec<_> = (100..110)
+            .map(|time| Vote::new(if time < 105 { b2.clone() } else { b3.clone() }, time))
+            .collect();
+        for vote in &recent {
+            assert!(native.push_vote(vote.clone(), &tree, &map, 32));
+        }
+        assert_eq!(stale.catchup(&recent, &tree), recent.len());
+        assert!(stale.verify_invariants(&tree));
+        assert_eq!(stale.export_votes(), native.export_votes());
+        assert_eq!(stale.branch_trunk.id, native.branch_trunk.id);
+        assert_eq!(stale.branch_trunk.id, 2);
+
+        // stops at the first vote that isn't descendent from the previous one
+        let forked = vec![Vote::new(b3.clone(), 110), Vote::new(b4.clone(), 111)];
+        assert_eq!(stale.catchup(&forked, &tree), 1);
+        assert!(stale.verify_invariants(&tree));
+        assert_eq!(stale.last_vote().unwrap().time, 110);
+        assert_eq!(stale.catchup(&[Vote::new(b3.clone(), 50)], &tree), 0);
     }
+
     #[test]
-    fn test_is_trunk_of_4() {
-        let mut tree = HashMap::new();
+    fn test_tower_state_diff() {
+        let mut tree = BranchTree::default();
+        let map = ConvergeMap::default();
         let b1 = Branch { id: 1, base: 0 };
         let b2 = Branch { id: 2, base: 1 };
-        tree.insert(b1.id, b1.clone());
-        assert!(b1.is_trunk_of(&b2, &tree));
-        assert!(!b2.is_trunk_of(&b1, &tree));
+        let b3 = Branch { id: 3, base: 1 };
+        let b4 = Branch { id: 4, base: 0 };
+        for b in &[&b1, &b2, &b3, &b4] {
+            tree.insert((*b).clone()).unwrap();
+        }
+        let times = |votes: &[&Vote]| -> Vec<usize> { votes.iter().map(|v| v.time).collect() };
+        let mut a = LockTower::new(32);
+        for time in 0..3 {
+            assert!(a.push_vote(Vote::new(b1.clone(), time), &tree, &map, 32));
+        }
+        let mut b = a.clone();
+        let diff = LockTower::tower_state_diff(&a, &b);
+        assert_eq!(times(&diff.common_votes), vec![2, 1, 0]);
+        assert!(diff.only_in_a.is_empty());
+        assert!(diff.only_in_b.is_empty());
+
+        // the towers share the votes on b1
+        assert!(a.push_vote(Vote::new(b2.clone(), 3), &tree, &map, 32));
+        assert!(b.push_vote(Vote::new(b3.clone(), 3), &tree, &map, 32));
+        assert!(b.push_vote(Vote::new(b3.clone(), 4), &tree, &map, 32));
+        let diff = LockTower::tower_state_diff(&a, &b);
+        assert_eq!(times(&diff.common_votes), vec![2, 1, 0]);
+        assert_eq!(times(&diff.only_in_a), vec![3]);
+        assert_eq!(times(&diff.only_in_b), vec![4, 3]);
+
+        let mut c = LockTower::new(32);
+        assert!(c.push_vote(Vote::new(b4.clone(), 1), &tree, &map, 32));
+        let diff = LockTower::tower_state_diff(&a, &c);
+        assert!(diff.common_votes.is_empty());
+        assert_eq!(times(&diff.only_in_a), vec![3, 2, 1, 0]);
+        assert_eq!(times(&diff.only_in_b), vec![1]);
     }
+
+    #[cfg(feature = "tracing")]
     #[test]
-    fn test_push_vote() {
-        let tree = HashMap::new();
-        let bmap = HashMap::new();
+    fn test_tracing() {
+        use std::io;
+        use std::sync::{Arc, Mutex};
+
+        #[derive(Clone)]
+        struct Capture(Arc<Mutex<Vec<u8>>>);
+        impl io::Write for Capture {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.0.lock().unwrap().write(buf)
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let capture = Capture(Arc::new(Mutex::new(vec![])));
+        let writer = capture.clone();
+        let subscriber = ::tracing_subscriber::fmt()
+            .with_max_level(::tracing::Level::DEBUG)
+            .with_ansi(false)
+            .with_writer(move || writer.clone())
+            .finish();
+        ::tracing::subscriber::with_default(subscriber, || {
+            let tree = BranchTree::default();
+            let map = ConvergeMap::default();
+            let b0 = Branch { id: 0, base: 0 };
+            let mut node = LockTower::new(4);
+            for time in 0..4 {
+                assert!(node.push_vote(Vote::new(b0.clone(), time), &tree, &map, 32));
+            }
+            assert!(node.push_vote(Vote::new(b0.clone(), 100), &tree, &map, 32));
+            let double = Vote::new(b0.clone(), 100);
+            assert!(node.try_push_vote(double, &tree, &map, 32).is_err());
+        });
+        let output = String::from_utf8(capture.0.lock().unwrap().clone()).unwrap();
+        let count = |patterns: &[&str]| {
+            output
+                .lines()
+                .filter(|l| patterns.iter().all(|p| l.contains(p)))
+                .count()
+        };
+        assert_eq!(count(&["DEBUG", "push_vote outcome=Ok(())"]), 5);
+        let double = "push_vote{branch=0 time=100}: locktower::locktower: push_vote outcome=Err";
+        assert_eq!(count(&["DEBUG", double]), 1);
+        assert_eq!(count(&["INFO", "time=3", "rooted branch=0"]), 1);
+        assert_eq!(count(&["WARN", "time=100", "rollback removed=3"]), 1);
+        assert_eq!(
+            count(&["DEBUG", "is_converged{depth=32}", "return=true"]),
+            5
+        );
+    }
+
+    #[test]
+    fn test_gossip_votes_subset() {
+        let tree = BranchTree::default();
+        let map = ConvergeMap::default();
         let b0 = Branch { id: 0, base: 0 };
         let mut node = LockTower::new(32);
-        let vote = Vote::new(b0.clone(), 0);
-        assert!(node.push_vote(vote, &tree, &bmap, 32));
-        assert_eq!(node.votes.len(), 1);
+        assert!(node.gossip_votes_subset(4).is_empty());
+        for time in 0..10 {
+            assert!(node.push_vote(Vote::new(b0.clone(), time), &tree, &map, 32));
+        }
+        let times = |max_votes| -> Vec<usize> {
+            node.gossip_votes_subset(max_votes)
+                .iter()
+                .map(|v| v.time)
+                .collect()
+        };
+        assert!(times(0).is_empty());
+        assert_eq!(times(1), vec![0]);
+        assert_eq!(times(2), vec![9, 0]);
+        // depths 0, 1, 2, 4, 8 and 9
+        assert_eq!(times(6), vec![9, 8, 7, 5, 1, 0]);
+        assert_eq!(times(8), vec![9, 8, 7, 6, 5, 4, 1, 0]);
+        let all: Vec<_> = (0..10).rev().collect();
+        assert_eq!(times(10), all);
+        assert_eq!(times(32), all);
+        for max_votes in 2..12 {
+            let subset = times(max_votes);
+            assert_eq!(subset.len(), max_votes.min(10));
+            assert_eq!(subset[0], 9);
+            assert_eq!(*subset.last().unwrap(), 0);
+        }
+    }
 
-        let vote = Vote::new(b0.clone(), 1);
-        assert!(node.push_vote(vote, &tree, &bmap, 32));
-        assert_eq!(node.votes.len(), 2);
+    #[test]
+    fn test_resize() {
+        let tree = BranchTree::default();
+        let map = ConvergeMap::default();
+        let b0 = Branch { id: 0, base: 0 };
+        let mut node = LockTower::new(4);
+        for time in 0..3 {
+            assert!(node.push_vote(Vote::new(b0.clone(), time), &tree, &map, 32));
+        }
+        assert_eq!(
+            node.resize(2),
+            Err(ResizeError::WouldTruncate { current_votes: 3 })
+        );
+        assert_eq!(node.resize(0), Err(ResizeError::ZeroSize));
+        assert_eq!(node.max_size, 4);
 
-        let vote = Vote::new(b0.clone(), 2);
-        assert!(node.push_vote(vote, &tree, &bmap, 32));
+        // growing doesn't change the votes
+        assert_eq!(node.resize(8), Ok(()));
         assert_eq!(node.votes.len(), 3);
+        assert_eq!(node.finalized_branches().count(), 0);
 
-        let vote = Vote::new(b0.clone(), 3);
-        assert!(node.push_vote(vote, &tree, &bmap, 32));
-        assert_eq!(node.votes.len(), 4);
-
-        assert_eq!(node.votes[0].lockout, 2);
-        assert_eq!(node.votes[1].lockout, 4);
-        assert_eq!(node.votes[2].lockout, 8);
-        assert_eq!(node.votes[3].lockout, 16);
+        // a full tower roots its oldest vote
+        assert_eq!(node.resize(3), Ok(()));
+        assert_eq!(node.max_size, 3);
+        let times: Vec<_> = node.votes.iter().map(|v| v.time).collect();
+        assert_eq!(times, vec![2, 1]);
+        assert_eq!(node.finalized_branches().count(), 1);
+        assert!(node.push_vote(Vote::new(b0.clone(), 3), &tree, &map, 32));
+        assert_eq!(node.votes.len(), 2);
+    }
 
-        assert_eq!(node.votes[1].lock_height(), 6);
-        assert_eq!(node.votes[2].lock_height(), 9);
+    #[test]
+    fn test_slot_commitment_level() {
+        let mut tree = BranchTree::default();
+        let map = ConvergeMap::default();
+        let branches: Vec<_> = (1..7).map(|id| Branch { id, base: id - 1 }).collect();
+        for b in &branches {
+            tree.insert(b.clone()).unwrap();
+        }
+        let mut node = LockTower::new(6);
+        let level = |node: &LockTower, slot: usize| {
+            node.slot_commitment_level(slot, &branches[slot], &tree)
+        };
+        assert_eq!(level(&node, 0), CommitmentLevel::Processed);
+        for (time, b) in branches.iter().take(4).enumerate() {
+            assert!(node.push_vote(Vote::new(b.clone(), time), &tree, &map, 32));
+        }
+        // lockouts are 16, 8, 4 and 2
+        for slot in 0..4 {
+            assert_eq!(level(&node, slot), CommitmentLevel::Processed);
+        }
+        assert!(node.push_vote(Vote::new(branches[4].clone(), 4), &tree, &map, 32));
+        assert_eq!(level(&node, 0), CommitmentLevel::Confirmed);
+        assert_eq!(level(&node, 1), CommitmentLevel::Processed);
+        // a different branch at the same slot
+        assert_eq!(
+            node.slot_commitment_level(0, &branches[1], &tree),
+            CommitmentLevel::Processed
+        );
 
-        let vote = Vote::new(b0.clone(), 7);
-        assert!(node.push_vote(vote, &tree, &bmap, 32));
+        assert!(node.push_vote(Vote::new(branches[5].clone(), 5), &tree, &map, 32));
+        assert_eq!(node.branch_trunk.id, 1);
+        assert_eq!(level(&node, 0), CommitmentLevel::Finalized);
+        assert_eq!(level(&node, 1), CommitmentLevel::Confirmed);
+        assert_eq!(level(&node, 5), CommitmentLevel::Processed);
+    }
 
-        assert_eq!(node.votes[0].lockout, 2);
+    #[test]
+    fn test_vote_memo() {
+        let tree = BranchTree::default();
+        let map = ConvergeMap::default();
+        let b0 = Branch { id: 0, base: 0 };
+        assert_eq!(Vote::new(b0.clone(), 0).memo(), None);
+        let mut plain = LockTower::new(4);
+        let mut memo = LockTower::new(4);
+        for time in 0..6 {
+            let m = [time as u8; 32];
+            assert!(plain.push_vote(Vote::new(b0.clone(), time), &tree, &map, 32));
+            assert!(memo.push_vote(Vote::new_with_memo(b0.clone(), time, m), &tree, &map, 32));
+            assert_eq!(memo.last_vote().unwrap().memo(), Some(m));
+        }
+        assert_eq!(plain.export_votes(), memo.export_votes());
+        let memos: Vec<_> = memo.votes.iter().map(|v| v.memo().unwrap()[0]).collect();
+        assert_eq!(memos, vec![5, 4, 3]);
+        assert!(plain.votes.iter().all(|v| v.memo().is_none()));
+    }
 
-        let b1 = Branch { id: 1, base: 1 };
-        let vote = Vote::new(b1.clone(), 8);
-        assert!(!node.push_vote(vote, &tree, &bmap, 32));
+    #[test]
+    fn test_prune_votes_before() {
+        let tree = BranchTree::default();
+        let map = ConvergeMap::default();
+        let b0 = Branch { id: 0, base: 0 };
+        let mut node = LockTower::new(32);
+        node.enable_audit_log();
+        for time in 0..4 {
+            assert!(node.push_vote(Vote::new(b0.clone(), time), &tree, &map, 32));
+        }
+        // lockouts are 16, 8, 4, 2, so votes 0 and 1 are still locked out at 2
+        assert_eq!(node.prune_votes_before(2), 2);
+        assert!(node.verify_invariants(&tree));
+        let times: Vec<_> = node.votes.iter().map(|v| v.time).collect();
+        assert_eq!(times, vec![3, 2]);
+        let pruned = node.take_audit_log().unwrap().events.pop();
+        match pruned {
+            Some(TowerEvent::PrunedWhileActive(votes)) => {
+                let times: Vec<_> = votes.iter().map(|v| v.time).collect();
+                assert_eq!(times, vec![0, 1]);
+            }
+            other => panic!("unexpected event {:?}", other),
+        }
 
-        let vote = Vote::new(b0.clone(), 8);
-        assert!(node.push_vote(vote, &tree, &bmap, 32));
+        node.enable_audit_log();
+        assert_eq!(node.prune_votes_before(2), 0);
+        assert_eq!(node.prune_votes_before(100), 2);
+        assert!(node.votes.is_empty());
+        assert!(node.verify_invariants(&tree));
+        assert!(node.take_audit_log().unwrap().events.is_empty());
+    }
 
-        assert_eq!(node.votes.len(), 4);
-        assert_eq!(node.votes[0].lockout, 2);
-        assert_eq!(node.votes[1].lockout, 4);
-        assert_eq!(node.votes[2].lockout, 8);
-        assert_eq!(node.votes[3].lockout, 16);
+    #[test]
+    fn test_audit_log() {
+        let mut tree = BranchTree::default();
+        let map = ConvergeMap::default();
+        let b1 = Branch { id: 1, base: 0 };
+        let b2 = Branch { id: 2, base: 0 };
+        tree.insert(b1.clone()).unwrap();
+        tree.insert(b2.clone()).unwrap();
+        let mut node = LockTower::new(3);
+        assert!(node.push_vote(Vote::new(b1.clone(), 0), &tree, &map, 32));
+        assert!(node.take_audit_log().is_none());
 
-        let vote = Vote::new(b0.clone(), 10);
-        assert!(node.push_vote(vote, &tree, &bmap, 32));
-        assert_eq!(node.votes.len(), 2);
-        assert_eq!(node.votes[0].lockout, 2);
-        assert_eq!(node.votes[1].lockout, 16);
+        node.enable_audit_log();
+        assert!(node.push_vote(Vote::new(b1.clone(), 1), &tree, &map, 32));
+        assert!(!node.push_vote(Vote::new(b2.clone(), 2), &tree, &map, 32));
+        assert!(node.push_vote(Vote::new(b1.clone(), 2), &tree, &map, 32));
+        assert!(node.push_vote(Vote::new(b1.clone(), 20), &tree, &map, 32));
+        let log = node.take_audit_log().unwrap();
+        let events: Vec<_> = log
+            .events
+            .iter()
+            .map(|e| match e {
+                TowerEvent::VoteAccepted(v) => format!("accepted {} {}", v.branch.id, v.time),
+                TowerEvent::VoteRejected { vote, reason } => {
+                    format!("rejected {} {} {:?}", vote.branch.id, vote.time, reason)
+                }
+                TowerEvent::VotesRolledBack(votes) => {
+                    let times: Vec<_> = votes.iter().map(|v| v.time).collect();
+                    format!("rolled back {:?}", times)
+                }
+                TowerEvent::BranchRooted(b) => format!("rooted {}", b.id),
+                TowerEvent::PrunedWhileActive(votes) => format!("pruned {}", votes.len()),
+            }).collect();
+        assert_eq!(
+            events,
+            vec![
+                "accepted 1 1",
+                "rejected 2 2 InvalidBranch",
+                "accepted 1 2",
+                "rooted 1",
+                "rolled back [1, 2]",
+                "accepted 1 20",
+            ]
+        );
+        assert!(node.take_audit_log().is_none());
     }
 
+    #[allow(clippy::useless_conversion)]
     fn create_network(sz: usize) -> Vec<LockTower> {
         (0..sz).into_iter().map(|_| LockTower::new(32)).collect()
     }
 
     /// The "height" or "depth" of this branch. How many branches until it connects to branch 0
-    fn calc_branch_depth(branch_tree: &HashMap<usize, Branch>, id: usize) -> usize {
+    fn calc_branch_depth(branch_tree: &BranchTree, id: usize) -> usize {
         let mut depth = 0;
-        let mut start = branch_tree.get(&id);
+        let mut start = branch_tree.get(id);
         loop {
             if start.is_none() {
                 break;
             }
             depth += 1;
-            start = branch_tree.get(&start.unwrap().base);
+            start = branch_tree.get(start.unwrap().base);
         }
         depth
     }
     /// map of `branch id` to `node count`
     /// This map contains how many nodes have the branch as an ancestor
     /// The branch with the highest count that is the newest is the network "trunk"
+    #[allow(clippy::ptr_arg)]
     fn calc_branch_map(
         network: &Vec<LockTower>,
-        branch_tree: &HashMap<usize, Branch>,
+        branch_tree: &BranchTree,
     ) -> HashMap<usize, usize> {
         let mut lca_map: HashMap<usize, usize> = HashMap::new();
         for node in network {
             let mut start = node.last_branch();
             loop {
                 *lca_map.entry(start.id).or_insert(0) += 1;
-                if branch_tree.get(&start.base).is_none() {
+                if branch_tree.get(start.base).is_none() {
                     break;
                 }
-                start = branch_tree.get(&start.base).unwrap().clone();
+                start = branch_tree.get(start.base).unwrap().clone();
             }
         }
         lca_map
     }
     /// find the branch with the highest count of nodes that have it as an ancestor
-    /// as well as with the highest possible branch id, which indicates it is the newest
-    fn calc_newest_trunk(bmap: &HashMap<usize, usize>) -> (usize, usize) {
-        let mut data: Vec<_> = bmap.iter().collect();
-        data.sort_by_key(|x| (x.1, x.0));
-        data.last().map(|v| (*v.0, *v.1)).unwrap()
+    /// as well as with the highest depth, which indicates it is the newest
+    /// the branch id breaks ties between branches of equal depth
+    fn calc_newest_trunk(bmap: &HashMap<usize, usize>, branch_tree: &BranchTree) -> (usize, usize) {
+        calc_newest_trunk_v2(&ConvergeMap::from_branch_map(bmap), branch_tree).unwrap()
     }
     /// how common is the latest branch of all the nodes
+    #[allow(clippy::ptr_arg)]
     fn calc_tip_converged(network: &Vec<LockTower>, bmap: &HashMap<usize, usize>) -> usize {
-        let sum: usize = network
-            .iter()
-            .map(|n| *bmap.get(&n.last_branch().id).unwrap_or(&0))
-            .sum();
-        sum / network.len()
+        simulator::calc_tip_converged(network, &ConvergeMap::from_branch_map(bmap))
+    }
+    #[test]
+    fn test_branch_depth_cmp() {
+        let mut tree = BranchTree::default();
+        let b1 = Branch { id: 1, base: 0 };
+        let b2 = Branch { id: 2, base: 1 };
+        let b5 = Branch { id: 5, base: 0 };
+        tree.insert(b1.clone()).unwrap();
+        tree.insert(b2.clone()).unwrap();
+        tree.insert(b5.clone()).unwrap();
+        assert_eq!(b2.depth(&tree), 2);
+        assert_eq!(branch_depth_cmp(&b2, &b5, &tree), Ordering::Greater);
+        assert_eq!(branch_depth_cmp(&b1, &b5, &tree), Ordering::Equal);
+
+        // the deeper branch wins with equal vote counts
+        let mut bmap = HashMap::new();
+        bmap.insert(2, 10);
+        bmap.insert(5, 10);
+        assert_eq!(calc_newest_trunk(&bmap, &tree), (2, 10));
+        // equal depth falls back to the branch id
+        bmap.insert(1, 10);
+        bmap.remove(&2);
+        assert_eq!(calc_newest_trunk(&bmap, &tree), (5, 10));
+    }
+    #[test]
+    fn test_expected_convergence_rounds() {
+        assert_eq!(expected_convergence_rounds(100, 100, 0.0), 0.0);
+        assert_eq!(expected_convergence_rounds(100, 100, 0.5), 0.0);
+        assert_eq!(expected_convergence_rounds(50, 100, 0.0), 50.0);
+        assert_eq!(expected_convergence_rounds(50, 100, 0.5), 100.0);
+        let mut last = 0.0;
+        for i in 0..10 {
+            let rounds = expected_convergence_rounds(50, 100, f64::from(i) / 10.0);
+            assert!(rounds > last);
+            last = rounds;
+        }
+    }
+    #[test]
+    fn test_calc_newest_trunk_v2() {
+        let mut tree = BranchTree::default();
+        let b1 = Branch { id: 1, base: 0 };
+        let b2 = Branch { id: 2, base: 1 };
+        let b3 = Branch { id: 3, base: 0 };
+        let b7 = Branch { id: 7, base: 0 };
+        for b in &[&b1, &b2, &b3, &b7] {
+            tree.insert((*b).clone()).unwrap();
+        }
+        let mut bmap = ConvergeMap::default();
+        assert_eq!(calc_newest_trunk_v2(&bmap, &tree), None);
+        // ids are not assigned in order, the deeper branch wins the tie
+        bmap.set(2, 5);
+        bmap.set(7, 5);
+        assert_eq!(calc_newest_trunk_v2(&bmap, &tree), Some((2, 5)));
+        // the higher count wins
+        bmap.set(1, 6);
+        assert_eq!(calc_newest_trunk_v2(&bmap, &tree), Some((1, 6)));
+        // equal count and equal depth falls back to the branch id
+        bmap.set(3, 6);
+        assert_eq!(calc_newest_trunk_v2(&bmap, &tree), Some((3, 6)));
     }
     #[test]
     fn test_no_partitions() {
-        let mut tree = HashMap::new();
+        let mut tree = BranchTree::default();
         let len = 100;
         let mut network = create_network(len);
         for rounds in 0..1 {
@@ -307,11 +6399,12 @@ mod test {
                     id: time + 1,
                     base: base.id,
                 };
-                tree.insert(branch.id, branch.clone());
+                tree.insert(branch.clone()).unwrap();
                 let vote = Vote::new(branch, time);
                 let bmap = calc_branch_map(&network, &tree);
+                let converge_map = ConvergeMap::from_branch_map(&bmap);
                 for node in network.iter_mut() {
-                    assert!(node.push_vote(vote.clone(), &tree, &bmap, 0));
+                    assert!(node.push_vote(vote.clone(), &tree, &converge_map, 0));
                 }
                 println!("{} {}", time, calc_tip_converged(&network, &bmap));
             }
@@ -319,24 +6412,72 @@ mod test {
         let bmap = calc_branch_map(&network, &tree);
         assert_eq!(calc_tip_converged(&network, &bmap), len);
     }
+    #[test]
+    fn test_ingest_network_votes() {
+        let mut tree = BranchTree::default();
+        let len = 100;
+        let mut network = create_network(len);
+        let mut ingested = create_network(len);
+        for i in 0..len {
+            let time = i;
+            let base = network[i].last_branch().clone();
+            let branch = Branch {
+                id: time + 1,
+                base: base.id,
+            };
+            tree.insert(branch.clone()).unwrap();
+            let vote = Vote::new(branch, time);
+            let bmap = calc_branch_map(&network, &tree);
+            let converge_map = ConvergeMap::from_branch_map(&bmap);
+            for node in network.iter_mut() {
+                assert!(node.push_vote(vote.clone(), &tree, &converge_map, 0));
+            }
+            let bmap = calc_branch_map(&ingested, &tree);
+            let round = NetworkVoteRound {
+                votes: vec![(i as u64, vote)],
+                converge_map: ConvergeMap::from_branch_map(&bmap),
+            };
+            for node in ingested.iter_mut() {
+                let result = node.ingest_network_votes(&round, &tree, 0);
+                assert_eq!(result.votes_applied, 1);
+                assert_eq!(result.tip.id, time + 1);
+            }
+        }
+        let bmap = calc_branch_map(&ingested, &tree);
+        assert_eq!(calc_tip_converged(&ingested, &bmap), len);
+        for (a, b) in network.iter().zip(ingested.iter()) {
+            assert_eq!(a.export_votes(), b.export_votes());
+            assert_eq!(a.branch_trunk.id, b.branch_trunk.id);
+        }
+
+        // replaying the last round is rejected as old votes
+        let last = NetworkVoteRound {
+            votes: vec![(0, Vote::new(ingested[0].last_branch(), len - 1))],
+            converge_map: ConvergeMap::default(),
+        };
+        let result = ingested[0].ingest_network_votes(&last, &tree, 0);
+        assert_eq!(result.votes_applied, 0);
+        assert_eq!(result.votes_rejected_old, 1);
+    }
     /// * num_partitions - 1 to 100 partitions
     /// * fail_rate - 0 to 1.0 rate of packet receive failure
     fn test_with_partitions(num_partitions: usize, fail_rate: f64) {
-        let mut tree = HashMap::new();
+        let mut tree = BranchTree::default();
         let len = 100;
         let mut network = create_network(len);
         let warmup = 8;
         for time in 0..warmup {
             let bmap = calc_branch_map(&network, &tree);
+            let converge_map = ConvergeMap::from_branch_map(&bmap);
             for node in network.iter_mut() {
                 let mut branch = node.last_branch().clone();
                 if branch.id == 0 {
                     branch.id = thread_rng().gen_range(1, 1 + num_partitions);
-                    tree.insert(branch.id, branch.clone());
+                    tree.insert(branch.clone()).unwrap();
                 }
                 let vote = Vote::new(branch, time);
                 assert!(node.is_valid(&vote, &tree));
-                assert!(node.push_vote(vote.clone(), &tree, &bmap, warmup));
+                assert!(node.push_vote(vote.clone(), &tree, &converge_map, warmup));
             }
         }
         for node in network.iter() {
@@ -354,17 +6495,18 @@ mod test {
                     id: time + num_partitions,
                     base: base.id,
                 };
-                tree.insert(branch.id, branch.clone());
+                tree.insert(branch.clone()).unwrap();
                 let bmap = calc_branch_map(&network, &tree);
+                let converge_map = ConvergeMap::from_branch_map(&bmap);
                 let vote = Vote::new(branch, time);
                 for node in network.iter_mut() {
                     if thread_rng().gen_range(0f64, 1.0f64) < fail_rate {
                         continue;
                     }
-                    node.push_vote(vote.clone(), &tree, &bmap, warmup);
+                    node.push_vote(vote.clone(), &tree, &converge_map, warmup);
                 }
                 let bmap = calc_branch_map(&network, &tree);
-                let trunk = calc_newest_trunk(&bmap);
+                let trunk = calc_newest_trunk(&bmap, &tree);
                 let trunk_time = if trunk.0 > num_partitions {
                     trunk.0 - num_partitions
                 } else {
@@ -389,7 +6531,7 @@ mod test {
             }
         }
         let bmap = calc_branch_map(&network, &tree);
-        let trunk = calc_newest_trunk(&bmap);
+        let trunk = calc_newest_trunk(&bmap, &tree);
         assert_eq!(trunk.1, len);
     }
     #[test]
@@ -0,0 +1,420 @@
+use locktower::{
+    calc_newest_trunk_v2, Branch, BranchIdAllocator, BranchTree, ConvergeMap, LockTower, Vote,
+    VoteError, MAX_NETWORK_SIZE,
+};
+use rand::prng::XorShiftRng;
+use rand::{Rng, SeedableRng};
+
+/// rate of packet receive failure between every pair of voters
+/// `fail_rate(receiver, sender)` doesn't have to equal `fail_rate(sender, receiver)`
+#[derive(Clone, Debug)]
+pub struct PartitionMatrix(Vec<Vec<f64>>);
+
+impl PartitionMatrix {
+    /// every voter fails to receive from every voter at `fail_rate`
+    pub fn new_symmetric(n: usize, fail_rate: f64) -> Self {
+        PartitionMatrix(vec![vec![fail_rate; n]; n])
+    }
+    /// * matrix - `matrix[receiver][sender]` is the fail rate from `sender` to `receiver`
+    pub fn new_asymmetric(matrix: Vec<Vec<f64>>) -> Self {
+        assert!(matrix.iter().all(|row| row.len() == matrix.len()));
+        PartitionMatrix(matrix)
+    }
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+    pub fn fail_rate(&self, receiver: usize, sender: usize) -> f64 {
+        self.0[receiver][sender]
+    }
+    /// fail rate averaged over every pair of voters
+    pub fn mean_fail_rate(&self) -> f64 {
+        let sum: f64 = self.0.iter().flat_map(|row| row.iter()).sum();
+        sum / (self.len() * self.len()) as f64
+    }
+}
+
+/// map of `branch id` to `node count`
+/// This map contains how many nodes have the branch as an ancestor
+pub fn calc_converge_map(network: &[LockTower], branch_tree: &BranchTree) -> ConvergeMap {
+    let mut converge_map = ConvergeMap::default();
+    for node in network {
+        let mut start = node.last_branch();
+        loop {
+            converge_map.increment(start.id());
+            match branch_tree.get(start.base()) {
+                Some(base) => start = base.clone(),
+                None => break,
+            }
+        }
+    }
+    converge_map
+}
+
+/// how common is the latest branch of all the nodes, 0 for an empty network
+pub fn calc_tip_converged(network: &[LockTower], converge_map: &ConvergeMap) -> usize {
+    if network.is_empty() {
+        return 0;
+    }
+    let sum: usize = network
+        .iter()
+        .map(|n| converge_map.get(n.last_branch().id()))
+        .sum();
+    sum / network.len()
+}
+
+/// convergence of the network at the end of a round
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RoundStats {
+    pub round: usize,
+    pub time: usize,
+    /// average number of voters that have each voter's last branch as an ancestor
+    pub tip_convergence: usize,
+    pub trunk_id: usize,
+    /// number of voters that have the trunk as an ancestor
+    pub trunk_convergence: usize,
+    pub trunk_depth: usize,
+}
+
+/// what happened to the votes passed to `inject_equivocation`, in the order they were pushed
+#[derive(Clone, Debug, Default)]
+pub struct InjectionResult {
+    pub accepted: Vec<Vote>,
+    pub rejected: Vec<(Vote, VoteError)>,
+}
+
+/// reason a `NetworkSimulator` cannot be created
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SimulatorError {
+    /// the convergence check only supports up to `MAX_NETWORK_SIZE` voters
+    NetworkTooLarge { len: usize, max_len: usize },
+}
+
+/// a network of voters that all start at branch 0
+/// every transmission generates a new branch on top of the sender's last branch
+pub struct NetworkSimulator {
+    network: Vec<LockTower>,
+    branch_tree: BranchTree,
+    partitions: PartitionMatrix,
+    allocator: BranchIdAllocator,
+    /// depth passed to `push_vote`
+    depth: usize,
+    time: usize,
+    rng: XorShiftRng,
+    history: Vec<RoundStats>,
+}
+
+impl NetworkSimulator {
+    /// the network size is the size of the partition matrix, at most `MAX_NETWORK_SIZE`
+    /// runs with the same `seed` are identical
+    pub fn new(
+        partitions: PartitionMatrix,
+        max_size: usize,
+        depth: usize,
+        seed: u64,
+    ) -> Result<Self, SimulatorError> {
+        if partitions.len() > MAX_NETWORK_SIZE {
+            return Err(SimulatorError::NetworkTooLarge {
+                len: partitions.len(),
+                max_len: MAX_NETWORK_SIZE,
+            });
+        }
+        Ok(Self {
+            network: (0..partitions.len())
+                .map(|_| LockTower::new(max_size))
+                .collect(),
+            branch_tree: BranchTree::default(),
+            partitions,
+            allocator: BranchIdAllocator::new(),
+            depth,
+            time: 0,
+            rng: XorShiftRng::seed_from_u64(seed),
+            history: vec![],
+        })
+    }
+    pub fn network(&self) -> &[LockTower] {
+        &self.network
+    }
+    pub fn branch_tree(&self) -> &BranchTree {
+        &self.branch_tree
+    }
+    pub fn time(&self) -> usize {
+        self.time
+    }
+    /// stats of every `run_round`, oldest first
+    pub fn history(&self) -> &[RoundStats] {
+        &self.history
+    }
+    /// split the network into `num_partitions` branches off of branch 0
+    /// and have every voter vote `rounds` times on its partition's branch
+    pub fn warmup(&mut self, num_partitions: usize, rounds: usize) {
+        for id in 1..=num_partitions {
+            self.allocator.reserve(id);
+        }
+        for _ in 0..rounds {
+            let converge_map = calc_converge_map(&self.network, &self.branch_tree);
+            for node in &mut self.network {
+                let mut branch = node.last_branch();
+                if branch.id() == 0 {
+                    branch = Branch::new(self.rng.gen_range(1, 1 + num_partitions), 0);
+                    self.branch_tree.insert(branch.clone()).unwrap();
+                }
+                let vote = Vote::new(branch, self.time);
+                assert!(node.push_vote(vote, &self.branch_tree, &converge_map, rounds));
+            }
+            self.time += 1;
+        }
+    }
+    /// each voter in turn transmits a vote for a new branch
+    pub fn run_round(&mut self) -> RoundStats {
+        for sender in 0..self.network.len() {
+            self.transmit(sender);
+        }
+        let converge_map = calc_converge_map(&self.network, &self.branch_tree);
+        let (trunk_id, trunk_convergence) =
+            calc_newest_trunk_v2(&converge_map, &self.branch_tree).unwrap_or((0, 0));
+        let trunk_depth = self
+            .branch_tree
+            .get(trunk_id)
+            .map(|b| b.depth(&self.branch_tree))
+            .unwrap_or(0);
+        let stats = RoundStats {
+            round: self.history.len(),
+            time: self.time,
+            tip_convergence: calc_tip_converged(&self.network, &converge_map),
+            trunk_id,
+            trunk_convergence,
+            trunk_depth,
+        };
+        self.history.push(stats.clone());
+        stats
+    }
+    /// `sender` transmits a vote for a new branch on top of its last branch
+    /// each receiver drops the vote at the partition matrix's fail rate
+    pub fn transmit(&mut self, sender: usize) {
+        let base = self.network[sender].last_branch();
+        let branch = self.allocator.next(base.id());
+        self.branch_tree.insert(branch.clone()).unwrap();
+        let converge_map = calc_converge_map(&self.network, &self.branch_tree);
+        let vote = Vote::new(branch, self.time);
+        for receiver in 0..self.network.len() {
+            if self.rng.gen_range(0f64, 1.0f64) < self.partitions.fail_rate(receiver, sender) {
+                continue;
+            }
+            self.network[receiver].push_vote(
+                vote.clone(),
+                &self.branch_tree,
+                &converge_map,
+                self.depth,
+            );
+        }
+        self.time += 1;
+    }
+    /// push two conflicting votes to `node_ix`, as an equivocating validator would
+    /// branches that are not in the branch tree yet are added and kept from being allocated,
+    /// a vote whose branch can't be added is rejected with `VoteError::InvalidBranch`
+    pub fn inject_equivocation(
+        &mut self,
+        node_ix: usize,
+        vote_a: Vote,
+        vote_b: Vote,
+    ) -> InjectionResult {
+        let mut result = InjectionResult::default();
+        let mut push = |vote: Vote| {
+            let id = vote.branch().id();
+            if id != 0 && !self.branch_tree.contains(id) {
+                if self.branch_tree.insert(vote.branch().clone()).is_err() {
+                    result.rejected.push((vote, VoteError::InvalidBranch));
+                    return;
+                }
+                self.allocator.reserve(id);
+            }
+            let converge_map = calc_converge_map(&self.network, &self.branch_tree);
+            match self.network[node_ix].try_push_vote(
+                vote.clone(),
+                &self.branch_tree,
+                &converge_map,
+                self.depth,
+            ) {
+                Ok(()) => result.accepted.push(vote),
+                Err(err) => result.rejected.push((vote, err)),
+            }
+        };
+        push(vote_a);
+        push(vote_b);
+        result
+    }
+    /// `(branch id, count)` of the newest branch with the most voters that have it as an ancestor
+    pub fn trunk(&self) -> Option<(usize, usize)> {
+        let converge_map = calc_converge_map(&self.network, &self.branch_tree);
+        calc_newest_trunk_v2(&converge_map, &self.branch_tree)
+    }
+    /// every voter has a common branch that is newer than branch 0
+    pub fn is_converged(&self) -> bool {
+        self.trunk()
+            .map(|(_, count)| count == self.network.len())
+            .unwrap_or(false)
+    }
+    /// transmit until the network converges
+    /// returns the number of transmissions it took
+    pub fn run_until_converged(&mut self, max_rounds: usize) -> Option<usize> {
+        let start = self.time;
+        for _ in 0..max_rounds {
+            for sender in 0..self.network.len() {
+                self.transmit(sender);
+                if self.is_converged() {
+                    return Some(self.time - start);
+                }
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_partition_matrix() {
+        let matrix = PartitionMatrix::new_symmetric(4, 0.25);
+        assert_eq!(matrix.len(), 4);
+        assert_eq!(matrix.fail_rate(1, 2), matrix.fail_rate(2, 1));
+        assert_eq!(matrix.mean_fail_rate(), 0.25);
+
+        let matrix = PartitionMatrix::new_asymmetric(vec![vec![0.0, 1.0], vec![0.0, 0.0]]);
+        assert_eq!(matrix.fail_rate(0, 1), 1.0);
+        assert_eq!(matrix.fail_rate(1, 0), 0.0);
+        assert_eq!(matrix.mean_fail_rate(), 0.25);
+    }
+    #[test]
+    fn test_no_failures_converge() {
+        let partitions = PartitionMatrix::new_symmetric(100, 0.0);
+        let mut sim = NetworkSimulator::new(partitions, 32, 8, 0).unwrap();
+        assert_eq!(sim.run_until_converged(1), Some(1));
+    }
+    #[test]
+    fn test_network_too_large() {
+        let partitions = PartitionMatrix::new_symmetric(101, 0.0);
+        assert_eq!(
+            NetworkSimulator::new(partitions, 32, 8, 0).err(),
+            Some(SimulatorError::NetworkTooLarge {
+                len: 101,
+                max_len: 100
+            })
+        );
+    }
+    #[test]
+    fn test_round_stats() {
+        let len = 100;
+        let partitions = PartitionMatrix::new_symmetric(len, 0.25);
+        let mut sim = NetworkSimulator::new(partitions, 32, 8, 0).unwrap();
+        sim.warmup(4, 8);
+        for round in 0..6 {
+            let stats = sim.run_round();
+            assert_eq!(stats.round, round);
+            assert_eq!(stats.time, sim.time());
+            assert_eq!(&stats, sim.history().last().unwrap());
+        }
+        let history = sim.history();
+        assert_eq!(history.len(), 6);
+        assert!(history.iter().all(|s| s.tip_convergence <= len));
+        assert!(history.iter().all(|s| s.trunk_convergence <= len));
+        let depths: Vec<_> = history.iter().map(|s| s.trunk_depth).collect();
+        assert!(depths.windows(2).all(|d| d[0] <= d[1]));
+    }
+    #[test]
+    fn test_inject_equivocation() {
+        let partitions = PartitionMatrix::new_symmetric(4, 0.0);
+        let mut sim = NetworkSimulator::new(partitions, 32, 8, 0).unwrap();
+        sim.warmup(1, 2);
+        let time = sim.time();
+        let vote_a = Vote::new(Branch::new(100, 1), time);
+        let vote_b = Vote::new(Branch::new(101, 1), time);
+        let result = sim.inject_equivocation(0, vote_a, vote_b);
+        assert_eq!(result.accepted.len(), 1);
+        assert_eq!(result.accepted[0].branch().id(), 100);
+        assert_eq!(result.rejected.len(), 1);
+        assert_eq!(result.rejected[0].0.branch().id(), 101);
+        assert_eq!(result.rejected[0].1, VoteError::DoubleVote { time });
+
+        let node = &sim.network()[0];
+        assert_eq!(node.last_branch().id(), 100);
+        assert!(node.verify_invariants(sim.branch_tree()));
+        assert!(node.double_vote_proof(time).is_some());
+        // the rest of the network keeps running on top of the injected branches
+        sim.run_round();
+        let tree = sim.branch_tree();
+        assert!(sim.network().iter().all(|n| n.verify_invariants(tree)));
+
+        // branches already in the tree are reused, a conflicting branch is rejected
+        let time = sim.time();
+        let base = sim.network()[1].last_branch().id();
+        let vote_a = Vote::new(Branch::new(102, base), time);
+        let result = sim.inject_equivocation(1, vote_a.clone(), vote_a);
+        assert_eq!(result.accepted.len(), 1);
+        assert_eq!(result.rejected[0].1, VoteError::DoubleVote { time });
+        let vote_b = Vote::new(Branch::new(101, 100), time + 1);
+        let result = sim.inject_equivocation(2, vote_b, Vote::new(Branch::new(100, 1), time + 1));
+        assert_eq!(result.rejected[0].1, VoteError::InvalidBranch);
+        assert_eq!(sim.branch_tree().get(101).unwrap().base(), 1);
+    }
+    /// the first half of the network can't receive from the second half
+    fn deaf_half(len: usize) -> PartitionMatrix {
+        let deaf = |receiver: usize, sender: usize| receiver < len / 2 && sender >= len / 2;
+        let matrix = (0..len)
+            .map(|receiver| {
+                (0..len)
+                    .map(|sender| if deaf(receiver, sender) { 1.0 } else { 0.0 })
+                    .collect()
+            }).collect();
+        PartitionMatrix::new_asymmetric(matrix)
+    }
+    #[test]
+    fn test_calc_tip_converged_empty() {
+        let tree = BranchTree::default();
+        let converge_map = calc_converge_map(&[], &tree);
+        assert_eq!(calc_tip_converged(&[], &converge_map), 0);
+    }
+    /// half of the network can't receive from the other half
+    #[test]
+    fn test_asymmetric_partition() {
+        let len = 100;
+        let asymmetric = deaf_half(len);
+        assert_eq!(asymmetric.mean_fail_rate(), 0.25);
+        let mut sim = NetworkSimulator::new(asymmetric, 32, 8, 0).unwrap();
+        sim.warmup(2, 8);
+        assert!(!sim.is_converged());
+
+        let before: Vec<_> = sim.network().iter().map(|n| n.last_branch().id()).collect();
+        sim.transmit(len - 1);
+        let after: Vec<_> = sim.network().iter().map(|n| n.last_branch().id()).collect();
+        let changed: Vec<_> = (0..len).filter(|i| before[*i] != after[*i]).collect();
+        assert!(!changed.is_empty());
+        assert!(changed.iter().all(|i| *i >= len / 2));
+
+        // everyone can receive from the deaf half, so the network still converges
+        assert!(sim.run_until_converged(40).is_some());
+    }
+    /// the same mean fail rate converges slower when it is all on one side of the partition
+    #[test]
+    fn test_asymmetric_partition_converges_slower() {
+        let len = 20;
+        let max_rounds = 50;
+        let transmissions = |partitions: &PartitionMatrix| -> usize {
+            (0..5)
+                .map(|seed| {
+                    let mut sim = NetworkSimulator::new(partitions.clone(), 32, 8, seed).unwrap();
+                    sim.warmup(2, 2);
+                    // a run that never converges counts as every transmission
+                    sim.run_until_converged(max_rounds)
+                        .unwrap_or(max_rounds * len)
+                }).sum()
+        };
+        let asymmetric = deaf_half(len);
+        let symmetric = PartitionMatrix::new_symmetric(len, asymmetric.mean_fail_rate());
+        assert!(transmissions(&asymmetric) > transmissions(&symmetric));
+    }
+}